@@ -0,0 +1,398 @@
+//! Data-driven ambience.
+//!
+//! Soundscapes are described in RON files under `assets/ambience`,
+//! named after the file. Each has looping beds and randomized
+//! one-shots, which an [`AmbienceEvent`] fades in and out together.
+//!
+//! ```ron
+//! (
+//!     beds: [
+//!         (sample: "pine_trees.ogg", name: Some("pine"), volume: 1.1),
+//!     ],
+//!     one_shots: [
+//!         (
+//!             samples: ["caw.ogg"],
+//!             interval: (start: 10.0, end: 25.0),
+//!             position: Some((x: (start: -20.0, end: -10.0), y: (start: 15.0, end: 15.0))),
+//!         ),
+//!     ],
+//! )
+//! ```
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use rand::{Rng, seq::SliceRandom};
+use serde::{Deserialize, Deserializer};
+use std::{
+    ops::RangeInclusive,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+use walkdir::WalkDir;
+
+use crate::audio::{AudioEvent, VolumeFadeEvent, loading::SampleLoader, repeater::SoundRepeater};
+
+pub fn ambience_plugin(app: &mut App) {
+    app.init_resource::<ActiveAmbience>()
+        .add_systems(PreStartup, load_soundscapes)
+        .add_observer(observe_ambience_event);
+}
+
+/// A set of sounds that make up a scene's ambience.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Soundscape {
+    #[serde(default)]
+    pub beds: Vec<Bed>,
+    #[serde(default)]
+    pub one_shots: Vec<OneShot>,
+}
+
+/// A looping sound that plays for as long as the soundscape does.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Bed {
+    #[serde(deserialize_with = "leak_str")]
+    pub sample: &'static str,
+    /// The name of the bed's voice, for use with [`VolumeFadeEvent`].
+    ///
+    /// Unnamed beds are given a unique name when they're loaded.
+    #[serde(default, deserialize_with = "leak_optional_str")]
+    pub name: Option<&'static str>,
+    #[serde(default = "full_volume")]
+    pub volume: f32,
+    #[serde(default)]
+    pub position: Option<(f32, f32)>,
+    /// Play a four-channel AmbiX recording around the listener.
+    #[serde(default)]
+    pub ambisonic: bool,
+}
+
+/// Sounds played at random intervals, volumes, and positions.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OneShot {
+    /// Each time, one of these samples is chosen at random.
+    #[serde(deserialize_with = "leak_strs")]
+    pub samples: Vec<&'static str>,
+    /// The time between sounds, in seconds.
+    pub interval: RangeInclusive<f32>,
+    #[serde(default = "full_volume_range")]
+    pub volume: RangeInclusive<f32>,
+    #[serde(default = "full_volume_range")]
+    pub speed: RangeInclusive<f32>,
+    /// Where the sounds come from. `None` plays them without spatialization.
+    #[serde(default)]
+    pub position: Option<PositionRange>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PositionRange {
+    pub x: RangeInclusive<f32>,
+    pub y: RangeInclusive<f32>,
+}
+
+fn full_volume() -> f32 {
+    1.0
+}
+
+fn full_volume_range() -> RangeInclusive<f32> {
+    1.0..=1.0
+}
+
+// Events need `'static` names, and soundscapes live for the
+// whole program, so we simply leak their strings on load.
+
+fn leak_str<'de, D: Deserializer<'de>>(deserializer: D) -> Result<&'static str, D::Error> {
+    String::deserialize(deserializer).map(|s| &*s.leak())
+}
+
+fn leak_optional_str<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<&'static str>, D::Error> {
+    Option::<String>::deserialize(deserializer).map(|s| s.map(|s| &*s.leak()))
+}
+
+fn leak_strs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<&'static str>, D::Error> {
+    Vec::<String>::deserialize(deserializer)
+        .map(|strings| strings.into_iter().map(|s| &*s.leak()).collect())
+}
+
+/// Every soundscape found in `assets/ambience`, by file stem.
+#[derive(Resource, Default)]
+pub struct Soundscapes(HashMap<String, Soundscape>);
+
+impl Soundscapes {
+    pub fn get(&self, name: &str) -> Option<&Soundscape> {
+        self.0.get(name)
+    }
+}
+
+/// Parse every soundscape in `assets/ambience`, by file stem.
+pub fn read_soundscapes() -> HashMap<String, Result<Soundscape, String>> {
+    let ambience_path = std::path::Path::new("assets/ambience");
+
+    let mut soundscapes = HashMap::default();
+    for entry in WalkDir::new(ambience_path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if path.extension().is_none_or(|e| e != "ron") {
+            continue;
+        }
+
+        let Some(name) = path.file_stem().map(|s| s.to_string_lossy().into_owned()) else {
+            continue;
+        };
+
+        let soundscape = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| ron::from_str::<Soundscape>(&contents).map_err(|e| e.to_string()));
+
+        soundscapes.insert(name, soundscape);
+    }
+
+    soundscapes
+}
+
+fn load_soundscapes(mut loader: ResMut<SampleLoader>, mut commands: Commands) {
+    let mut soundscapes = HashMap::default();
+    for (name, soundscape) in read_soundscapes() {
+        let mut soundscape = match soundscape {
+            Ok(soundscape) => soundscape,
+            Err(e) => {
+                warn!("failed to load soundscape {name}: {e}");
+                continue;
+            }
+        };
+
+        loader.reference(soundscape.beds.iter().map(|bed| bed.sample));
+        loader.reference(
+            soundscape
+                .one_shots
+                .iter()
+                .flat_map(|one_shot| one_shot.samples.iter().copied()),
+        );
+
+        for (i, bed) in soundscape.beds.iter_mut().enumerate() {
+            bed.name
+                .get_or_insert_with(|| &*format!("ambience-{name}-{i}").leak());
+        }
+
+        soundscapes.insert(name, soundscape);
+    }
+
+    commands.insert_resource(Soundscapes(soundscapes));
+}
+
+/// Change which soundscapes are playing.
+#[derive(Event, Debug, Clone)]
+pub enum AmbienceEvent {
+    /// Fade in a single soundscape, fading out all others.
+    Switch {
+        soundscape: &'static str,
+        seconds: f32,
+    },
+    /// Fade one soundscape to a level between 0 and 1, leaving the rest alone.
+    Blend {
+        soundscape: &'static str,
+        level: f32,
+        seconds: f32,
+    },
+}
+
+/// The soundscapes currently playing.
+#[derive(Resource, Default)]
+struct ActiveAmbience(HashMap<&'static str, ActiveSoundscape>);
+
+struct ActiveSoundscape {
+    level: f32,
+    /// The level applied to one-shots, stored as `f32` bits.
+    one_shot_level: Arc<AtomicU32>,
+    repeaters: Vec<Entity>,
+}
+
+fn observe_ambience_event(
+    trigger: Trigger<AmbienceEvent>,
+    soundscapes: Res<Soundscapes>,
+    mut active: ResMut<ActiveAmbience>,
+    named: Query<(Entity, &Name)>,
+    mut commands: Commands,
+) -> Result {
+    let (soundscape, level, seconds) = match *trigger {
+        AmbienceEvent::Switch {
+            soundscape,
+            seconds,
+        } => {
+            let others: Vec<_> = active
+                .0
+                .keys()
+                .copied()
+                .filter(|name| *name != soundscape)
+                .collect();
+
+            for other in others {
+                set_level(
+                    other,
+                    0.0,
+                    seconds,
+                    &soundscapes,
+                    &mut active,
+                    &named,
+                    &mut commands,
+                )?;
+            }
+
+            (soundscape, 1.0, seconds)
+        }
+        AmbienceEvent::Blend {
+            soundscape,
+            level,
+            seconds,
+        } => (soundscape, level.clamp(0.0, 1.0), seconds),
+    };
+
+    set_level(
+        soundscape,
+        level,
+        seconds,
+        &soundscapes,
+        &mut active,
+        &named,
+        &mut commands,
+    )
+}
+
+/// Fade a soundscape to `level`, starting or stopping it as needed.
+fn set_level(
+    name: &'static str,
+    level: f32,
+    seconds: f32,
+    soundscapes: &Soundscapes,
+    active: &mut ActiveAmbience,
+    named: &Query<(Entity, &Name)>,
+    commands: &mut Commands,
+) -> Result {
+    let soundscape = soundscapes
+        .get(name)
+        .ok_or_else(|| format!("unknown soundscape \"{name}\""))?;
+
+    let current = match active.0.get(name) {
+        Some(playing) => playing.level,
+        None if level > 0.0 => {
+            active
+                .0
+                .insert(name, start_soundscape(soundscape, commands));
+            0.0
+        }
+        None => return Ok(()),
+    };
+
+    if current == level {
+        return Ok(());
+    }
+
+    let stopping = level == 0.0;
+
+    for bed in &soundscape.beds {
+        let bed_name = bed.name.unwrap_or_default();
+
+        commands.trigger(VolumeFadeEvent {
+            name: bed_name,
+            start: bed.volume * current,
+            end: bed.volume * level,
+            seconds,
+            stop: stopping,
+        });
+
+        // Stopping beds give up their name, so the soundscape
+        // can start again before they've faded out.
+        if stopping {
+            let bed_name = Name::new(bed_name);
+            for (entity, name) in named {
+                if name == &bed_name {
+                    commands
+                        .entity(entity)
+                        .insert(Name::new(format!("{bed_name}-outgoing")));
+                }
+            }
+        }
+    }
+
+    if stopping {
+        if let Some(playing) = active.0.remove(name) {
+            for repeater in playing.repeaters {
+                commands.entity(repeater).despawn();
+            }
+        }
+    } else if let Some(playing) = active.0.get_mut(name) {
+        playing.level = level;
+        playing
+            .one_shot_level
+            .store(level.to_bits(), Ordering::Relaxed);
+    }
+
+    Ok(())
+}
+
+/// Start a soundscape's beds silently and spawn its one-shot repeaters.
+fn start_soundscape(soundscape: &Soundscape, commands: &mut Commands) -> ActiveSoundscape {
+    for bed in &soundscape.beds {
+        commands.trigger(AudioEvent {
+            sample: bed.sample,
+            position: bed.position.map(|(x, y)| Vec2::new(x, y)),
+            volume: 0.0,
+            looping: true,
+            name: bed.name,
+            ambisonic: bed.ambisonic,
+            ..Default::default()
+        });
+    }
+
+    let one_shot_level = Arc::new(AtomicU32::new(0f32.to_bits()));
+
+    let repeaters = soundscape
+        .one_shots
+        .iter()
+        .filter(|one_shot| !one_shot.samples.is_empty())
+        .map(|one_shot| {
+            let sound = one_shot.clone();
+            let interval = one_shot.interval.clone();
+            let level = one_shot_level.clone();
+
+            commands
+                .spawn(SoundRepeater::new(
+                    move || {
+                        let mut rng = rand::thread_rng();
+                        let level = f32::from_bits(level.load(Ordering::Relaxed));
+
+                        AudioEvent {
+                            sample: sound.samples.choose(&mut rng).copied().unwrap_or_default(),
+                            position: sound.position.as_ref().map(|range| {
+                                Vec2::new(
+                                    rng.gen_range(range.x.clone()),
+                                    rng.gen_range(range.y.clone()),
+                                )
+                            }),
+                            volume: rng.gen_range(sound.volume.clone()) * level,
+                            speed: rng.gen_range(sound.speed.clone()),
+                            ..Default::default()
+                        }
+                    },
+                    move || {
+                        let mut rng = rand::thread_rng();
+                        let duration = rng.gen_range(interval.clone());
+
+                        Duration::from_secs_f32(duration)
+                    },
+                ))
+                .id()
+        })
+        .collect();
+
+    ActiveSoundscape {
+        level: 0.0,
+        one_shot_level,
+        repeaters,
+    }
+}
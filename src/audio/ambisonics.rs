@@ -0,0 +1,154 @@
+//! First-order Ambisonics beds.
+//!
+//! Events marked [`ambisonic`][crate::audio::AudioEvent::ambisonic] play
+//! their four-channel sample as first-order AmbiX (ACN channel order,
+//! SN3D normalization). Instead of being spatialized like a point source,
+//! it's decoded to the output layout with virtual cardioid microphones
+//! that turn with the listener.
+//!
+//! The listener faces wherever the camera does, and can be turned
+//! with the Q and E keys.
+
+use bevy::prelude::*;
+
+use crate::audio::{
+    AudioEvent,
+    panning::{OutputLayout, SpeakerLayout},
+};
+
+pub fn ambisonics_plugin(app: &mut App) {
+    app.init_resource::<ListenerOrientation>()
+        .add_systems(Update, turn_listener)
+        .add_systems(
+            PostUpdate,
+            (sync_listener_orientation, compute_decode_matrices).chain(),
+        );
+}
+
+/// The number of channels in a first-order Ambisonics stream.
+pub const AMBISONIC_CHANNELS: usize = 4;
+
+/// How quickly the listener turns, in radians per second.
+const TURN_SPEED: f32 = core::f32::consts::FRAC_PI_2;
+
+/// Returns `true` if an event's sample, with this many channels, should be decoded as Ambisonics.
+///
+/// Beds are marked explicitly, since a four-channel file could just as
+/// well be quad. A marked sample without four channels is played normally.
+pub fn is_ambisonic(event: &AudioEvent, channels: usize) -> bool {
+    if !event.ambisonic {
+        return false;
+    }
+
+    if channels != AMBISONIC_CHANNELS {
+        warn!(
+            "playing \"{}\" normally, since Ambisonic beds need {AMBISONIC_CHANNELS} channels, not {channels}",
+            event.sample
+        );
+        return false;
+    }
+
+    true
+}
+
+/// The direction the listener faces.
+///
+/// The yaw is in radians, with positive values turning to the right.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq)]
+pub struct ListenerOrientation {
+    pub yaw: f32,
+}
+
+/// A playing Ambisonics bed.
+///
+/// Engines insert this on their voices when the sample is Ambisonic.
+#[derive(Component, Debug, Default)]
+#[require(DecodeMatrix)]
+pub struct AmbisonicBed;
+
+/// The per-output-channel weights of each Ambisonic channel, in ACN order.
+#[derive(Component, Debug, Default, Clone, PartialEq)]
+pub struct DecodeMatrix(pub Vec<[f32; AMBISONIC_CHANNELS]>);
+
+/// Calculate a decoding matrix for the given layout and listener yaw.
+pub fn decode_matrix(layout: SpeakerLayout, yaw: f32) -> Vec<[f32; AMBISONIC_CHANNELS]> {
+    // Stereo is decoded with a wider pair of virtual microphones than
+    // the speakers themselves, otherwise the bed collapses to the center.
+    let azimuths: &[Option<f32>] = match layout {
+        SpeakerLayout::Stereo => &[Some(-90.0), Some(90.0)],
+        _ => layout.azimuths(),
+    };
+
+    let speakers = azimuths.iter().filter(|a| a.is_some()).count() as f32;
+    let scale = (2.0 / speakers).sqrt();
+
+    azimuths
+        .iter()
+        .map(|azimuth| {
+            let Some(azimuth) = azimuth else {
+                return [0.0; AMBISONIC_CHANNELS];
+            };
+
+            // Turning the listener is the same as turning every speaker with it.
+            let world_azimuth = azimuth.to_radians() + yaw;
+
+            // AmbiX's Y axis points left, while azimuths here increase to the right.
+            let w = 0.5;
+            let y = -0.5 * world_azimuth.sin();
+            let x = 0.5 * world_azimuth.cos();
+
+            [w * scale, y * scale, 0.0, x * scale]
+        })
+        .collect()
+}
+
+/// Turn the camera, and so the listener, with the Q and E keys.
+fn turn_listener(
+    mut camera: Query<&mut Transform, With<Camera2d>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+) {
+    let Ok(mut transform) = camera.single_mut() else {
+        return;
+    };
+
+    let mut turn = 0.0;
+    if keys.pressed(KeyCode::KeyQ) {
+        turn -= 1.0;
+    }
+    if keys.pressed(KeyCode::KeyE) {
+        turn += 1.0;
+    }
+
+    // Turning right is a clockwise roll.
+    if turn != 0.0 {
+        transform.rotate_z(-turn * TURN_SPEED * time.delta_secs());
+    }
+}
+
+fn sync_listener_orientation(
+    camera: Query<&Transform, With<Camera2d>>,
+    mut orientation: ResMut<ListenerOrientation>,
+) {
+    let Ok(transform) = camera.single() else {
+        return;
+    };
+
+    // A counter-clockwise camera rotation turns the listener to the left.
+    let (_, _, roll) = transform.rotation.to_euler(EulerRot::XYZ);
+    orientation.set_if_neq(ListenerOrientation { yaw: -roll });
+}
+
+fn compute_decode_matrices(
+    mut beds: Query<&mut DecodeMatrix, With<AmbisonicBed>>,
+    orientation: Res<ListenerOrientation>,
+    layout: Res<OutputLayout>,
+) {
+    let matrix = decode_matrix(layout.0, orientation.yaw);
+
+    for mut bed in &mut beds {
+        if bed.0 != matrix {
+            bed.0.clone_from(&matrix);
+        }
+    }
+}
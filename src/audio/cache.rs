@@ -0,0 +1,355 @@
+//! A cache of decoded samples on disk.
+//!
+//! Decoding and resampling every sample on each launch adds up for a
+//! large library. The [`AudioSampleLoader`][crate::audio::sample::AudioSampleLoader]
+//! instead stores what it decodes, along with any measured loudness, and
+//! reads it back on the next launch.
+//!
+//! Entries are named by a hash of the sample's path, and keyed by a hash
+//! of the source file and the target sample rate, so an edited file or a
+//! different output device simply misses. Writing a new entry for a
+//! sample removes any made from older versions of its file, along with
+//! any writes of it that were interrupted.
+
+use bevy::prelude::*;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+pub fn cache_plugin(app: &mut App) {
+    app.init_resource::<SampleCache>();
+}
+
+/// Identifies the first bytes of a cache entry, and its layout version.
+const MAGIC: &[u8; 8] = b"SMPLPCM1";
+
+/// Where decoded samples are cached.
+///
+/// Engines copy this into their sample loader, so it should be
+/// inserted before they're added.
+#[derive(Resource, Debug, Clone)]
+pub struct SampleCache {
+    /// `None` disables the cache.
+    pub directory: Option<PathBuf>,
+}
+
+impl Default for SampleCache {
+    fn default() -> Self {
+        Self {
+            directory: Some(PathBuf::from("cache/samples")),
+        }
+    }
+}
+
+/// Identifies a decoded sample in the cache.
+#[derive(Debug, Clone)]
+pub struct CacheKey {
+    /// The sample's path, for reporting.
+    sample: String,
+    /// A hash of the sample's path, which prefixes its entries.
+    ///
+    /// Flattening the path into a file name could give two samples the
+    /// same name, so the full path is hashed instead.
+    path_hash: u64,
+    hash: u64,
+    sample_rate: u32,
+}
+
+impl CacheKey {
+    pub fn new(sample: &str, bytes: &[u8], sample_rate: u32) -> Self {
+        Self {
+            sample: sample.to_string(),
+            path_hash: content_hash(sample.as_bytes()),
+            hash: content_hash(bytes),
+            sample_rate,
+        }
+    }
+
+    fn file_name(&self) -> String {
+        format!(
+            "{:016x}.{:016x}.{}.pcm",
+            self.path_hash, self.hash, self.sample_rate
+        )
+    }
+}
+
+/// A 64-bit FNV-1a hash, which is stable across builds and platforms.
+fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// A decoded sample, as stored in the cache.
+#[derive(Debug, Clone)]
+pub struct CachedSample {
+    pub channels: Vec<Vec<f32>>,
+    pub sample_rate: u32,
+    /// The sample's integrated loudness, if it was measured.
+    ///
+    /// A measured sample can still have no loudness if it's silent.
+    pub loudness: Option<Option<f32>>,
+}
+
+impl SampleCache {
+    /// Read a sample back from the cache.
+    ///
+    /// Missing and unreadable entries are both treated as a miss.
+    pub fn read(&self, key: &CacheKey) -> Option<CachedSample> {
+        let path = self.directory.as_ref()?.join(key.file_name());
+        let bytes = fs::read(path).ok()?;
+
+        decode_entry(&bytes)
+    }
+
+    /// Store a sample, replacing entries made from other versions of its file.
+    pub fn write(&self, key: &CacheKey, sample: &CachedSample) {
+        let Some(directory) = &self.directory else {
+            return;
+        };
+
+        if let Err(e) = write_entry(directory, key, sample) {
+            warn!("failed to cache sample {}: {e}", key.sample);
+            return;
+        }
+
+        remove_stale(directory, key);
+    }
+}
+
+fn write_entry(directory: &Path, key: &CacheKey, sample: &CachedSample) -> io::Result<()> {
+    fs::create_dir_all(directory)?;
+
+    let frames = sample.channels.first().map(Vec::len).unwrap_or(0);
+    let mut bytes = Vec::with_capacity(32 + frames * sample.channels.len() * 4);
+
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&(sample.channels.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&sample.sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(frames as u64).to_le_bytes());
+
+    let (measured, loudness) = match sample.loudness {
+        Some(loudness) => (1u8, loudness.unwrap_or(f32::NEG_INFINITY)),
+        None => (0, 0.0),
+    };
+    bytes.push(measured);
+    bytes.extend_from_slice(&loudness.to_le_bytes());
+
+    for channel in &sample.channels {
+        for value in channel {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    // Entries are written in full before they're renamed into place,
+    // so an interrupted write never leaves a truncated entry behind.
+    let temporary = directory.join(format!("{}.tmp", key.file_name()));
+    fs::File::create(&temporary)?.write_all(&bytes)?;
+    fs::rename(temporary, directory.join(key.file_name()))
+}
+
+fn decode_entry(bytes: &[u8]) -> Option<CachedSample> {
+    let (magic, rest) = bytes.split_at_checked(MAGIC.len())?;
+    if magic != MAGIC {
+        return None;
+    }
+
+    let (header, data) = rest.split_at_checked(21)?;
+    let channels = u32::from_le_bytes(header[0..4].try_into().ok()?) as usize;
+    let sample_rate = u32::from_le_bytes(header[4..8].try_into().ok()?);
+    let frames = u64::from_le_bytes(header[8..16].try_into().ok()?) as usize;
+    let loudness = f32::from_le_bytes(header[17..21].try_into().ok()?);
+
+    let loudness = match header[16] {
+        0 => None,
+        _ if loudness == f32::NEG_INFINITY => Some(None),
+        _ => Some(Some(loudness)),
+    };
+
+    if channels == 0 || frames == 0 || data.len() != channels * frames * 4 {
+        return None;
+    }
+
+    let channels = data
+        .chunks_exact(frames * 4)
+        .map(|channel| {
+            channel
+                .chunks_exact(4)
+                .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                .collect()
+        })
+        .collect();
+
+    Some(CachedSample {
+        channels,
+        sample_rate,
+        loudness,
+    })
+}
+
+/// Remove this sample's entries that were made from a different file,
+/// and any of its writes that were interrupted before being renamed into place.
+fn remove_stale(directory: &Path, key: &CacheKey) {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return;
+    };
+
+    let prefix = format!("{:016x}.", key.path_hash);
+    let current = format!("{prefix}{:016x}.", key.hash);
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+
+        let Some(rest) = file_name.strip_prefix(&prefix) else {
+            continue;
+        };
+
+        // Only files shaped like our entries are touched.
+        let (is_entry, is_temporary) = match rest.split('.').collect::<Vec<_>>().as_slice() {
+            [hash, rate, "pcm"] => (is_entry_key(hash, rate), false),
+            [hash, rate, "pcm", "tmp"] => (false, is_entry_key(hash, rate)),
+            _ => (false, false),
+        };
+
+        // The current entry's temporary file has already been renamed,
+        // so any left over are from interrupted writes.
+        if is_temporary || (is_entry && !file_name.starts_with(&current)) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+fn is_entry_key(hash: &str, rate: &str) -> bool {
+    hash.len() == 16
+        && hash.chars().all(|c| c.is_ascii_hexdigit())
+        && !rate.is_empty()
+        && rate.chars().all(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh, empty directory for a single test.
+    fn directory(test: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("sample-cache-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        directory
+    }
+
+    fn sample(loudness: Option<Option<f32>>) -> CachedSample {
+        CachedSample {
+            channels: vec![vec![0.0, 0.5, -1.0, 0.25], vec![1.0, -0.5, 0.0, -0.25]],
+            sample_rate: 44_100,
+            loudness,
+        }
+    }
+
+    fn entries(directory: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+
+        names
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let directory = directory("round-trip");
+
+        for loudness in [None, Some(None), Some(Some(-18.5))] {
+            let key = CacheKey::new("music/theme.ogg", b"theme", 48_000);
+            let original = sample(loudness);
+
+            write_entry(&directory, &key, &original).unwrap();
+            let bytes = fs::read(directory.join(key.file_name())).unwrap();
+            let decoded = decode_entry(&bytes).unwrap();
+
+            assert_eq!(decoded.channels, original.channels);
+            assert_eq!(decoded.sample_rate, original.sample_rate);
+            assert_eq!(decoded.loudness, original.loudness);
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn similar_paths_have_their_own_entries() {
+        let nested = CacheKey::new("a/b.ogg", b"sample", 48_000);
+        let flat = CacheKey::new("a_b.ogg", b"sample", 48_000);
+
+        assert_ne!(nested.file_name(), flat.file_name());
+    }
+
+    #[test]
+    fn unfinished_and_foreign_entries_are_misses() {
+        let directory = directory("misses");
+        let cache = SampleCache {
+            directory: Some(directory.clone()),
+        };
+        let key = CacheKey::new("splash.ogg", b"splash", 48_000);
+
+        // A write that was interrupted before it was renamed into place.
+        write_entry(&directory, &key, &sample(None)).unwrap();
+        fs::rename(
+            directory.join(key.file_name()),
+            directory.join(format!("{}.tmp", key.file_name())),
+        )
+        .unwrap();
+        assert!(cache.read(&key).is_none());
+
+        // A truncated entry.
+        write_entry(&directory, &key, &sample(None)).unwrap();
+        let bytes = fs::read(directory.join(key.file_name())).unwrap();
+        fs::write(directory.join(key.file_name()), &bytes[..bytes.len() - 3]).unwrap();
+        assert!(cache.read(&key).is_none());
+
+        // Something else entirely.
+        fs::write(directory.join(key.file_name()), b"not a cached sample").unwrap();
+        assert!(cache.read(&key).is_none());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn only_stale_entries_for_the_same_path_are_removed() {
+        let directory = directory("stale");
+        let cache = SampleCache {
+            directory: Some(directory.clone()),
+        };
+
+        let old = CacheKey::new("creek.ogg", b"old creek", 48_000);
+        let old_rate = CacheKey::new("creek.ogg", b"old creek", 44_100);
+        let other = CacheKey::new("splash.ogg", b"old creek", 48_000);
+        let current = CacheKey::new("creek.ogg", b"new creek", 48_000);
+
+        for key in [&old, &old_rate, &other] {
+            write_entry(&directory, key, &sample(None)).unwrap();
+        }
+        let interrupted = format!("{}.tmp", old.file_name());
+        fs::write(directory.join(&interrupted), b"partial").unwrap();
+        fs::write(directory.join("notes.txt"), b"not ours").unwrap();
+
+        cache.write(&current, &sample(None));
+
+        let mut expected = vec![
+            current.file_name(),
+            other.file_name(),
+            "notes.txt".to_string(),
+        ];
+        expected.sort();
+        assert_eq!(entries(&directory), expected);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
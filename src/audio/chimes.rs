@@ -1,31 +1,53 @@
-use bevy::{platform::collections::HashSet, prelude::*};
-use rand::{Rng, seq::SliceRandom, thread_rng};
-use std::time::Duration;
+use bevy::{prelude::*, sprite::Anchor};
+use rand::{Rng, thread_rng};
+use std::{f32::consts::TAU, time::Duration};
 
-use crate::audio::AudioEvent;
+use crate::audio::{
+    AudioEvent, VoiceVolume,
+    clock::AudioClock,
+    modal::{ChimeEvent, ChimeVoice, ModalChime},
+    music::MusicPlayer,
+};
 
 pub fn chimes_plugin(app: &mut App) {
-    app.add_systems(Update, (trigger_chimes, hit_chimes).chain());
+    app.init_resource::<Wind>()
+        .init_resource::<ChimeVoice>()
+        .init_resource::<PointerSpeed>()
+        .add_systems(PreUpdate, track_pointer_speed)
+        .add_systems(
+            Update,
+            (
+                (follow_wind, trigger_chimes, swing_chimes).chain(),
+                spawn_chime_tubes,
+                swing_tubes,
+            ),
+        );
 }
 
-/// The chimes are a procedural effect, playing a set of randomly
-/// selected, spatialized chime samples.
+/// The chimes are a small physical simulation.
 ///
-/// The volume of the samples is reduced for every chime, and once
-/// below a threshold, the effect is halted.
+/// A striker hangs in the middle of a ring of tubes, pulled back
+/// to rest by a spring and pushed around by the [`Wind`]. Whenever
+/// it hits a tube, that tube's sample plays, louder the faster the
+/// striker was moving. Once the chimes have been quiet for a few
+/// seconds, the effect is halted.
 #[derive(Component)]
-pub struct ChimesTimer {
-    initial: bool,
-    timer: Timer,
-    amplitude: f32,
+pub struct WindChimes {
     position: Vec2,
-    played_samples: HashSet<usize>,
+    /// The striker's displacement from rest.
+    striker: Vec2,
+    velocity: Vec2,
+    /// The current gust, as a multiple of the wind's strength.
+    gust: f32,
+    /// The time since the last hit, in seconds.
+    idle: f32,
 }
 
 #[derive(Component)]
 pub struct ChimesEnable;
 
-const CHIMES: &[&str] = &[
+/// One sample per tube, in order around the ring.
+pub const CHIME_SAMPLES: &[&str] = &[
     "chimes/chime-d1.ogg",
     "chimes/chime-d2.ogg",
     "chimes/chime-e1.ogg",
@@ -40,18 +62,133 @@ const CHIMES: &[&str] = &[
     "chimes/chime-d5.ogg",
 ];
 
-impl ChimesTimer {
-    pub fn new(initial_amplitude: f32, position: Vec2) -> Self {
-        let mut rng = rand::thread_rng();
+/// The distance from the striker's rest position to the tubes.
+const TUBE_RADIUS: f32 = 0.1;
+/// The spring pulling the striker back to rest, per unit mass.
+const STIFFNESS: f32 = 25.0;
+const DAMPING: f32 = 1.2;
+/// How much of the striker's speed survives a hit.
+const RESTITUTION: f32 = 0.6;
+/// Slower contacts are too gentle to hear.
+const MIN_HIT_SPEED: f32 = 0.05;
+/// The sample volume for each meter per second of impact speed.
+const HIT_VOLUME: f32 = 1.5;
+const MAX_VOLUME: f32 = 1.3;
+/// The striker speed of a push with a strength of 1.
+const PUSH_SPEED: f32 = 2.0;
+/// The force of the wind at a strength of 1.
+const WIND_FORCE: f32 = 2.0;
+/// How quickly gusts settle back to the mean.
+const GUST_RETURN: f32 = 1.5;
+const GUST_JITTER: f32 = 1.2;
+/// Random sideways variation in the wind's direction.
+const TURBULENCE: f32 = 0.4;
+const IDLE_SECONDS: f32 = 3.0;
+/// The simulation is stepped at this interval so fast swings don't tunnel.
+const SUBSTEP: f32 = 1.0 / 240.0;
 
+impl WindChimes {
+    pub fn new(position: Vec2) -> Self {
         Self {
-            initial: true,
-            timer: Timer::new(Duration::from_secs_f32(rng.r#gen()), TimerMode::Repeating),
-            amplitude: initial_amplitude,
             position,
-            played_samples: HashSet::default(),
+            striker: Vec2::ZERO,
+            velocity: Vec2::ZERO,
+            gust: 1.0,
+            idle: 0.0,
         }
     }
+
+    /// Knock the striker in a random direction, as if brushed by a hand.
+    pub fn with_push(mut self, strength: f32) -> Self {
+        let angle = thread_rng().gen_range(0.0..TAU);
+        self.velocity += Vec2::from_angle(angle) * strength * PUSH_SPEED;
+        self
+    }
+}
+
+/// Strike a chime, placed by `event`.
+///
+/// Sampled chimes play `sample`, which is usually the tube's own recording.
+/// Synthesized chimes play the tube's degree of the music's key when
+/// it has one, or the voice's own scale otherwise.
+fn play_chime(
+    tube: usize,
+    sample: &'static str,
+    voice: ChimeVoice,
+    music: &MusicPlayer,
+    event: AudioEvent,
+    commands: &mut Commands,
+) {
+    match voice {
+        ChimeVoice::Sampled => commands.trigger(AudioEvent {
+            sample,
+            speed: 0.9,
+            ..event
+        }),
+        ChimeVoice::Synthesized(scale) => {
+            let scale = music
+                .current()
+                .and_then(|track| track.sounding_key())
+                .unwrap_or(scale);
+
+            commands.trigger(ChimeEvent {
+                chime: ModalChime::new(scale.frequency(tube)),
+                event,
+            });
+        }
+    }
+}
+
+/// The tube in the given direction from the striker's rest position.
+fn tube_at(direction: Vec2) -> usize {
+    let count = CHIME_SAMPLES.len();
+    let angle = direction.to_angle().rem_euclid(TAU);
+
+    (angle / TAU * count as f32).round() as usize % count
+}
+
+/// The wind blowing through any chimes.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Wind {
+    /// Zero is calm, while one is enough to keep the chimes ringing.
+    pub strength: f32,
+    pub direction: Vec2,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Self {
+            strength: 0.0,
+            direction: Vec2::X,
+        }
+    }
+}
+
+/// Ties the [`Wind`] strength to the volume of a named sound, like the wind in the trees.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct WindFollows {
+    pub name: &'static str,
+    /// The wind strength for each unit of volume.
+    pub scale: f32,
+}
+
+fn follow_wind(
+    follows: Option<Res<WindFollows>>,
+    voices: Query<(&Name, &VoiceVolume)>,
+    mut wind: ResMut<Wind>,
+) {
+    let Some(follows) = follows else {
+        return;
+    };
+
+    let follow_name = Name::new(follows.name);
+    for (name, volume) in &voices {
+        if name != &follow_name {
+            continue;
+        }
+
+        wind.strength = volume.0 * follows.scale;
+    }
 }
 
 fn trigger_chimes(
@@ -60,51 +197,245 @@ fn trigger_chimes(
     mut commands: Commands,
 ) {
     if keys.just_pressed(KeyCode::KeyC) {
-        commands.spawn(ChimesTimer::new(0.6, Vec2::new(10.0, 10.0)));
+        commands.spawn(WindChimes::new(Vec2::new(10.0, 10.0)).with_push(0.6));
     }
 }
 
-fn hit_chimes(
-    mut chimes: Query<(Entity, &mut ChimesTimer)>,
-    mut commands: Commands,
+fn swing_chimes(
+    mut chimes: Query<(Entity, &mut WindChimes)>,
+    wind: Res<Wind>,
     time: Res<Time>,
+    clock: Res<AudioClock>,
+    voice: Res<ChimeVoice>,
+    music: Res<MusicPlayer>,
+    mut commands: Commands,
 ) {
-    let delta = time.delta();
-    for (entity, mut timer) in &mut chimes {
-        if timer.initial || timer.timer.tick(delta).just_finished() {
-            timer.initial = false;
-            let mut rng = thread_rng();
-
-            timer.amplitude -= 0.03;
-            let new_duration = rng.gen_range(0.1..0.3);
-            timer
-                .timer
-                .set_duration(Duration::from_secs_f32(new_duration));
-
-            if timer.amplitude <= 0.15 {
-                commands.entity(entity).despawn();
+    let delta = time.delta_secs();
+    let steps = (delta / SUBSTEP).ceil().max(1.0) as usize;
+    let dt = delta / steps as f32;
+
+    let mut rng = thread_rng();
+
+    for (entity, mut chimes) in &mut chimes {
+        chimes.idle += delta;
+
+        for step in 0..steps {
+            // Gusts wander randomly around the wind's mean strength.
+            chimes.gust += (1.0 - chimes.gust) * GUST_RETURN * dt
+                + rng.gen_range(-1.0..1.0) * GUST_JITTER * dt.sqrt();
+
+            let turbulence = Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let force = (wind.direction.normalize_or_zero() + turbulence * TURBULENCE)
+                * wind.strength
+                * WIND_FORCE
+                * chimes.gust.max(0.0);
+
+            let acceleration = force - STIFFNESS * chimes.striker - DAMPING * chimes.velocity;
+            chimes.velocity += acceleration * dt;
+            let velocity = chimes.velocity;
+            chimes.striker += velocity * dt;
+
+            let distance = chimes.striker.length();
+            if distance < TUBE_RADIUS {
                 continue;
             }
 
-            if timer.played_samples.len() == CHIMES.len() {
-                timer.played_samples.clear();
+            let normal = chimes.striker / distance;
+            let speed = chimes.velocity.dot(normal);
+            chimes.striker = normal * TUBE_RADIUS;
+
+            if speed <= 0.0 {
+                continue;
             }
 
-            // get an unplayed sample
-            let next_sample = (0..CHIMES.len())
-                .filter(|i| !timer.played_samples.contains(i))
-                .collect::<Vec<_>>()
-                .choose(&mut rng)
-                .copied()
-                .unwrap();
-
-            commands.trigger(AudioEvent {
-                sample: CHIMES[next_sample],
-                position: Some(timer.position),
-                volume: timer.amplitude * 2.0,
-                speed: 0.9,
-                ..Default::default()
-            });
+            chimes.velocity -= (1.0 + RESTITUTION) * speed * normal;
+
+            if speed < MIN_HIT_SPEED {
+                continue;
+            }
+
+            chimes.idle = 0.0;
+
+            // The frame's hits are spread out on the clock the same way they were
+            // in the simulation.
+            let offset = Duration::from_secs_f32(dt * step as f32);
+
+            // The direction of the hit decides which tube sounds.
+            let tube = tube_at(normal);
+            play_chime(
+                tube,
+                CHIME_SAMPLES[tube],
+                *voice,
+                &music,
+                AudioEvent {
+                    position: Some(chimes.position),
+                    volume: (speed * HIT_VOLUME).min(MAX_VOLUME),
+                    start: Some(clock.horizon().after(offset)),
+                    ..Default::default()
+                },
+                &mut commands,
+            );
+        }
+
+        if chimes.idle > IDLE_SECONDS {
+            commands.entity(entity).despawn();
         }
     }
 }
+
+/// The scale between the screen and the audio world, with the listener at the center.
+const PIXELS_PER_METER: f32 = 40.0;
+
+/// A chime that can be struck by clicking it or dragging across it.
+#[derive(Component)]
+struct ChimeTube {
+    index: usize,
+    /// The current swing angle in radians.
+    angle: f32,
+    angular_velocity: f32,
+}
+
+/// How fast the mouse or touch is moving, in pixels per second.
+#[derive(Resource, Default)]
+struct PointerSpeed {
+    last_position: Option<Vec2>,
+    speed: f32,
+}
+
+fn track_pointer_speed(
+    window: Single<&Window>,
+    touches: Res<Touches>,
+    time: Res<Time>,
+    mut pointer: ResMut<PointerSpeed>,
+) {
+    let position = window
+        .cursor_position()
+        .or_else(|| touches.iter().next().map(|touch| touch.position()));
+
+    let delta = time.delta_secs();
+    pointer.speed = match (pointer.last_position, position) {
+        (Some(last), Some(current)) if delta > 0.0 => last.distance(current) / delta,
+        _ => 0.0,
+    };
+    pointer.last_position = position;
+}
+
+/// Lay out the tubes once the chimes become playable.
+fn spawn_chime_tubes(enabled: Query<(), Added<ChimesEnable>>, mut commands: Commands) {
+    if enabled.is_empty() {
+        return;
+    }
+
+    let spacing = 48.0;
+    let first = -spacing * (CHIME_SAMPLES.len() - 1) as f32 / 2.0;
+
+    for i in 0..CHIME_SAMPLES.len() {
+        // Higher chimes are shorter, like the real thing.
+        let length = 130.0 - 6.0 * i as f32;
+
+        commands
+            .spawn((
+                ChimeTube {
+                    index: i,
+                    angle: 0.0,
+                    angular_velocity: 0.0,
+                },
+                Sprite {
+                    color: Color::srgb(0.75, 0.78, 0.82),
+                    custom_size: Some(Vec2::new(14.0, length)),
+                    anchor: Anchor::TopCenter,
+                    ..Default::default()
+                },
+                Transform::from_xyz(first + spacing * i as f32, 330.0, 0.0),
+            ))
+            .observe(click_tube)
+            .observe(brush_tube);
+    }
+}
+
+fn click_tube(
+    trigger: Trigger<Pointer<Pressed>>,
+    mut tubes: Query<(&mut ChimeTube, &GlobalTransform)>,
+    pointer: Res<PointerSpeed>,
+    voice: Res<ChimeVoice>,
+    music: Res<MusicPlayer>,
+    mut commands: Commands,
+) -> Result {
+    let (mut tube, transform) = tubes.get_mut(trigger.target())?;
+
+    // A still click still deserves a clear note.
+    let volume = strike_volume(pointer.speed).max(0.6);
+    strike(&mut tube, transform, volume, *voice, &music, &mut commands);
+
+    Ok(())
+}
+
+fn brush_tube(
+    trigger: Trigger<Pointer<Over>>,
+    mut tubes: Query<(&mut ChimeTube, &GlobalTransform)>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    touches: Res<Touches>,
+    pointer: Res<PointerSpeed>,
+    voice: Res<ChimeVoice>,
+    music: Res<MusicPlayer>,
+    mut commands: Commands,
+) -> Result {
+    let dragging = mouse.pressed(MouseButton::Left) || touches.iter().next().is_some();
+    if !dragging {
+        return Ok(());
+    }
+
+    let (mut tube, transform) = tubes.get_mut(trigger.target())?;
+    strike(
+        &mut tube,
+        transform,
+        strike_volume(pointer.speed),
+        *voice,
+        &music,
+        &mut commands,
+    );
+
+    Ok(())
+}
+
+/// Faster gestures hit harder.
+fn strike_volume(pointer_speed: f32) -> f32 {
+    (pointer_speed / 1500.0).clamp(0.15, MAX_VOLUME)
+}
+
+fn strike(
+    tube: &mut ChimeTube,
+    transform: &GlobalTransform,
+    volume: f32,
+    voice: ChimeVoice,
+    music: &MusicPlayer,
+    commands: &mut Commands,
+) {
+    tube.angular_velocity += volume * 4.0;
+
+    play_chime(
+        tube.index,
+        CHIME_SAMPLES[tube.index],
+        voice,
+        music,
+        AudioEvent {
+            position: Some(transform.translation().xy() / PIXELS_PER_METER),
+            volume,
+            ..Default::default()
+        },
+        commands,
+    );
+}
+
+fn swing_tubes(mut tubes: Query<(&mut ChimeTube, &mut Transform)>, time: Res<Time>) {
+    let dt = time.delta_secs();
+
+    for (mut tube, mut transform) in &mut tubes {
+        let acceleration = -STIFFNESS * tube.angle - DAMPING * 2.0 * tube.angular_velocity;
+        tube.angular_velocity += acceleration * dt;
+        let angular_velocity = tube.angular_velocity;
+        tube.angle += angular_velocity * dt;
+
+        transform.rotation = Quat::from_rotation_z(tube.angle);
+    }
+}
@@ -0,0 +1,56 @@
+//! A shared audio clock for sample-accurate scheduling.
+//!
+//! Each engine advances the [`AudioClock`] from its own notion of
+//! time at the start of every frame. Since the audio thread keeps
+//! running between frames, events scheduled a little ahead of
+//! time with [`AudioEvent::start`][crate::audio::AudioEvent::start]
+//! land exactly when they should, regardless of frame rate.
+
+use bevy::prelude::*;
+use std::time::Duration;
+
+pub fn clock_plugin(app: &mut App) {
+    app.init_resource::<AudioClock>();
+}
+
+/// How far ahead of the audio clock utilities should schedule sounds.
+///
+/// This should comfortably exceed a frame, otherwise
+/// scheduled sounds may arrive late.
+pub const LOOKAHEAD: Duration = Duration::from_millis(100);
+
+/// A point in time on the audio clock, in seconds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
+pub struct AudioInstant(pub f64);
+
+impl AudioInstant {
+    pub fn after(self, duration: Duration) -> Self {
+        Self(self.0 + duration.as_secs_f64())
+    }
+
+    /// The time from `earlier` to `self`, saturating at zero.
+    pub fn since(self, earlier: AudioInstant) -> Duration {
+        Duration::from_secs_f64((self.0 - earlier.0).max(0.0))
+    }
+}
+
+/// When a voice was scheduled to start.
+///
+/// Engines insert this on voices played with a
+/// [`start`][crate::audio::AudioEvent::start], so sounds that are
+/// still waiting to play can be told apart from those already playing.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct ScheduledStart(pub AudioInstant);
+
+/// The engine's audio clock, as of the start of the frame.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct AudioClock {
+    pub now: AudioInstant,
+}
+
+impl AudioClock {
+    /// The latest point that should already be scheduled.
+    pub fn horizon(&self) -> AudioInstant {
+        self.now.after(LOOKAHEAD)
+    }
+}
@@ -1,25 +1,123 @@
 use bevy::prelude::*;
-use rand::{Rng, seq::SliceRandom};
 use std::time::Duration;
 
-use crate::audio::{AudioEvent, repeater::SoundRepeater};
+use crate::audio::{
+    AudioEvent,
+    clock::{AudioClock, AudioInstant, LOOKAHEAD},
+    pools::{PoolMode, RegisterSamplePool, SamplePool},
+    repeater::{SoundRepeater, StopRepeaterEvent},
+    rhythm::RhythmPattern,
+};
 
 pub fn footsteps_plugin(app: &mut App) {
-    app.add_observer(toggle_walking);
+    app.register_sample_pool(
+        Surface::Leaves.pool(),
+        SamplePool {
+            samples: LEAF_STEPS,
+            mode: PoolMode::NoRepeat(1),
+            speed: 0.95..=1.05,
+            ..Default::default()
+        },
+    )
+    .register_sample_pool(
+        Surface::Water.pool(),
+        SamplePool {
+            samples: WATER_STEPS,
+            speed: 1.5..=1.8,
+            volume: 0.25..=0.35,
+            ..Default::default()
+        },
+    )
+    .add_systems(PostUpdate, step_emitters)
+    .add_observer(toggle_walking);
 }
 
 /// The walk event allows us to control footsteps, which are really
 /// just a [`SoundRepeater`].
 #[derive(Event, Clone)]
 pub enum WalkEvent {
-    Start(f32),
+    Start(Walk),
+    /// Keep walking, but on a different surface.
+    Surface(Surface),
     Stop,
 }
 
+/// What the listener is walking on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Surface {
+    #[default]
+    Leaves,
+    Water,
+}
+
+impl Surface {
+    /// The name of the surface's [`SamplePool`].
+    pub fn pool(self) -> &'static str {
+        match self {
+            Surface::Leaves => "footsteps-leaves",
+            Surface::Water => "footsteps-water",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Walk {
+    pub volume: f32,
+    pub surface: Surface,
+    /// The walking speed in meters per second, which sets the cadence.
+    pub speed: f32,
+}
+
+impl Walk {
+    pub fn new(volume: f32) -> Self {
+        Self {
+            volume,
+            surface: Surface::Leaves,
+            speed: STROLL_SPEED,
+        }
+    }
+
+    pub fn on(mut self, surface: Surface) -> Self {
+        self.surface = surface;
+        self
+    }
+
+    pub fn at_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// The time between steps.
+    ///
+    /// This treats the stride as fixed, which holds up well enough at walking pace.
+    pub fn step_interval(&self) -> Duration {
+        let seconds = STRIDE / self.speed.max(f32::EPSILON);
+
+        Duration::from_secs_f32(seconds.clamp(0.25, 1.5))
+    }
+}
+
+/// The length of a single step, in meters.
+const STRIDE: f32 = 0.75;
+
+/// A leisurely walking speed, in meters per second.
+const STROLL_SPEED: f32 = 0.75;
+
+/// Where footsteps are heard relative to the listener.
+///
+/// Each step lands a little to the left or right of this.
+const FEET: Vec2 = Vec2::new(0.0, -1.5);
+
+/// The distance between the listener's feet.
+const STANCE: f32 = 0.5;
+
+/// The name of the footstep repeater.
+pub const FOOTSTEPS: &str = "footsteps";
+
 #[derive(Component)]
-struct Footsteps;
+struct Footsteps(Walk);
 
-const FOOTSTEPS: &[&str] = &[
+pub const LEAF_STEPS: &[&str] = &[
     "footsteps/step1.ogg",
     "footsteps/step2.ogg",
     "footsteps/step3.ogg",
@@ -30,53 +128,125 @@ const FOOTSTEPS: &[&str] = &[
     "footsteps/step8.ogg",
 ];
 
-fn toggle_walking(
-    trigger: Trigger<WalkEvent>,
-    walking: Query<Entity, With<Footsteps>>,
-    mut commands: Commands,
-) {
-    if let Ok(walking) = walking.single() {
-        commands.entity(walking).despawn();
+pub const WATER_STEPS: &[&str] = &["splash.ogg"];
+
+fn toggle_walking(trigger: Trigger<WalkEvent>, walking: Query<&Footsteps>, mut commands: Commands) {
+    let current = walking.single().ok().map(|footsteps| footsteps.0);
+
+    let next = match *trigger {
+        WalkEvent::Start(walk) => Some(walk),
+        // Changing surfaces only makes sense if we're already walking.
+        WalkEvent::Surface(surface) => current.map(|walk| walk.on(surface)),
+        WalkEvent::Stop => None,
+    };
+
+    if current.is_some() {
+        commands.trigger(StopRepeaterEvent {
+            name: FOOTSTEPS,
+            fade_out: None,
+        });
+    }
+
+    let Some(walk) = next else {
+        return;
+    };
+
+    let next_sound = move || AudioEvent {
+        sample: walk.surface.pool(),
+        position: Some(FEET),
+        volume: walk.volume,
+        ..Default::default()
+    };
+
+    // A slightly uneven gait sounds a lot more natural than a metronome.
+    let gait = RhythmPattern::gait(walk.step_interval(), 0.04, STANCE).with_humanize(0.06);
+
+    commands.spawn((
+        Footsteps(walk),
+        SoundRepeater::with_rhythm(next_sound, gait).with_name(FOOTSTEPS),
+    ));
+}
+
+/// Footsteps for a moving entity.
+///
+/// Steps are spaced by the distance the entity covers, so the cadence
+/// follows its speed, and each lands beside its path at its position.
+///
+/// Each step is placed on the [`AudioClock`] where the entity crossed
+/// the stride mark during the frame, and scheduled a [`LOOKAHEAD`] later,
+/// so the cadence stays even however the frames fall.
+#[derive(Component, Debug, Clone)]
+#[require(Transform)]
+pub struct FootstepEmitter {
+    pub volume: f32,
+    pub surface: Surface,
+    traveled: f32,
+    last_position: Option<Vec2>,
+    last_time: Option<AudioInstant>,
+    left_foot: bool,
+}
+
+impl FootstepEmitter {
+    pub fn new(volume: f32) -> Self {
+        Self {
+            volume,
+            surface: Surface::Leaves,
+            traveled: 0.0,
+            last_position: None,
+            last_time: None,
+            left_foot: true,
+        }
     }
 
-    match *trigger {
-        WalkEvent::Start(volume) => {
-            let mut rng = rand::thread_rng();
-            let mut last_sound = *FOOTSTEPS.choose(&mut rng).unwrap();
+    pub fn on(mut self, surface: Surface) -> Self {
+        self.surface = surface;
+        self
+    }
+}
 
-            let mut next_sound = move || {
-                let mut rng = rand::thread_rng();
+/// The most steps an emitter plays in a single frame.
+const MAX_STEPS_PER_FRAME: usize = 4;
 
-                let speed = rng.gen_range(0.95..1.05);
+fn step_emitters(
+    mut emitters: Query<(&mut FootstepEmitter, &Transform)>,
+    clock: Res<AudioClock>,
+    mut commands: Commands,
+) {
+    for (mut emitter, transform) in &mut emitters {
+        let position = transform.translation.xy();
+        let last_position = emitter.last_position.replace(position).unwrap_or(position);
+        let last_time = emitter.last_time.replace(clock.now).unwrap_or(clock.now);
 
-                let options = FOOTSTEPS
-                    .iter()
-                    .filter(|s| **s != last_sound)
-                    .collect::<Vec<_>>();
+        let movement = position - last_position;
+        let distance = movement.length();
+        let before = emitter.traveled;
+        emitter.traveled += distance;
 
-                let sample = **options.choose(&mut rng).unwrap();
-                last_sound = sample;
+        let direction = movement.normalize_or_zero();
+        let mut steps = 0;
 
-                AudioEvent {
-                    sample,
-                    speed,
-                    volume,
-                    ..Default::default()
-                }
-            };
+        while emitter.traveled >= STRIDE && steps < MAX_STEPS_PER_FRAME {
+            emitter.traveled -= STRIDE;
+            emitter.left_foot = !emitter.left_foot;
+            steps += 1;
 
-            commands.trigger(next_sound());
+            // How far through this frame's movement the stride mark was crossed.
+            let fraction = (steps as f32 * STRIDE - before) / distance;
+            let crossed_at = last_time.0 + (clock.now.0 - last_time.0) * fraction as f64;
 
-            commands.spawn((
-                Footsteps,
-                SoundRepeater::new(next_sound, || {
-                    let mut rng = rand::thread_rng();
-                    let delay = rng.gen_range(0.9..1.1);
+            let side = if emitter.left_foot { 1.0 } else { -1.0 };
+            let foot = direction.perp() * side * STANCE / 2.0;
 
-                    Duration::from_secs_f32(delay)
-                }),
-            ));
+            commands.trigger(AudioEvent {
+                sample: emitter.surface.pool(),
+                position: Some(last_position + movement * fraction + foot),
+                volume: emitter.volume,
+                start: Some(AudioInstant(crossed_at).after(LOOKAHEAD)),
+                ..Default::default()
+            });
         }
-        WalkEvent::Stop => {}
+
+        // Anything beyond a few steps is dropped rather than played all at once.
+        emitter.traveled %= STRIDE;
     }
 }
@@ -0,0 +1,310 @@
+//! On-demand sample loading.
+//!
+//! Samples are loaded through the [`AssetServer`] the first time they're
+//! played, or ahead of time as part of a preload group. Events for samples
+//! that are still loading wait until they're ready, and unloading a group
+//! releases every sample that no other loaded group needs.
+
+use bevy::{
+    asset::LoadState,
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+};
+
+use crate::audio::{
+    AudioEvent,
+    report::{SampleFailure, SampleLoadError},
+    sample::AudioSample,
+    stems::LayeredAudioEvent,
+};
+
+pub fn loading_plugin(app: &mut App) {
+    app.init_resource::<SampleLoader>()
+        .add_systems(PreUpdate, resume_pending)
+        .add_observer(observe_group_event);
+}
+
+/// An event that's waiting for its samples to load.
+#[derive(Debug, Clone)]
+pub enum PendingEvent {
+    Sample(AudioEvent),
+    Layered(LayeredAudioEvent),
+}
+
+impl From<AudioEvent> for PendingEvent {
+    fn from(event: AudioEvent) -> Self {
+        PendingEvent::Sample(event)
+    }
+}
+
+impl From<LayeredAudioEvent> for PendingEvent {
+    fn from(event: LayeredAudioEvent) -> Self {
+        PendingEvent::Layered(event)
+    }
+}
+
+/// Keeps samples loaded by path, and holds back events until their samples are ready.
+#[derive(Resource, Default)]
+pub struct SampleLoader {
+    handles: HashMap<&'static str, Handle<AudioSample>>,
+    groups: HashMap<&'static str, &'static [&'static str]>,
+    /// Samples played outside of any group, like streamed music.
+    referenced: HashSet<&'static str>,
+    loaded_groups: HashSet<&'static str>,
+    /// Loaded groups that haven't yet announced they're ready.
+    announcing: HashSet<&'static str>,
+    pending: Vec<(Vec<Handle<AudioSample>>, PendingEvent)>,
+}
+
+impl SampleLoader {
+    /// The handle for a sample path, starting to load it if needed.
+    pub fn load(&mut self, path: &'static str, server: &AssetServer) -> Handle<AudioSample> {
+        self.handles
+            .entry(path)
+            .or_insert_with(|| server.load(path))
+            .clone()
+    }
+
+    /// The handle an event should play.
+    pub fn handle(&mut self, event: &AudioEvent, server: &AssetServer) -> Handle<AudioSample> {
+        match &event.handle {
+            Some(handle) => handle.clone(),
+            None => self.load(event.sample, server),
+        }
+    }
+
+    /// Check whether the samples are ready to play.
+    ///
+    /// Samples that failed to load are an error.
+    pub fn ensure<'a>(
+        &self,
+        handles: impl IntoIterator<Item = &'a Handle<AudioSample>>,
+        server: &AssetServer,
+    ) -> Result<bool> {
+        let mut ready = true;
+        for handle in handles {
+            match server.load_state(handle) {
+                LoadState::Loaded => {}
+                LoadState::NotLoaded | LoadState::Loading => ready = false,
+                LoadState::Failed(e) => {
+                    let path = server
+                        .get_path(handle)
+                        .map(|path| path.path().to_string_lossy().into_owned())
+                        .unwrap_or_default();
+
+                    return Err(SampleLoadError::new(path, SampleFailure::from(&*e)).into());
+                }
+            }
+        }
+
+        Ok(ready)
+    }
+
+    /// Hold an event until its samples are ready, after which it's triggered again.
+    pub fn defer(&mut self, event: impl Into<PendingEvent>, handles: Vec<Handle<AudioSample>>) {
+        self.pending.push((handles, event.into()));
+    }
+
+    /// Note samples that are played without being in a group.
+    pub fn reference(&mut self, samples: impl IntoIterator<Item = &'static str>) {
+        self.referenced.extend(samples);
+    }
+
+    /// Every sample in a registered group, along with every other referenced sample.
+    pub fn referenced(&self) -> impl Iterator<Item = &'static str> {
+        let samples: HashSet<_> = self
+            .groups
+            .values()
+            .flat_map(|samples| samples.iter().copied())
+            .chain(self.referenced.iter().copied())
+            .collect();
+
+        samples.into_iter()
+    }
+
+    /// Whether every sample in a group has finished loading.
+    pub fn group_loaded(&self, group: &str, server: &AssetServer) -> bool {
+        self.loaded_groups.contains(group)
+            && self.groups.get(group).is_some_and(|samples| {
+                samples.iter().all(|sample| {
+                    self.handles
+                        .get(sample)
+                        .is_none_or(|handle| !is_loading(server, handle))
+                })
+            })
+    }
+}
+
+fn is_loading(server: &AssetServer, handle: &Handle<AudioSample>) -> bool {
+    matches!(
+        server.load_state(handle),
+        LoadState::NotLoaded | LoadState::Loading
+    )
+}
+
+fn resume_pending(
+    mut loader: ResMut<SampleLoader>,
+    server: Res<AssetServer>,
+    mut commands: Commands,
+) {
+    let loader = &mut *loader;
+
+    loader.pending.retain(|(handles, event)| {
+        let waiting = handles.iter().any(|handle| is_loading(&server, handle));
+
+        if !waiting {
+            match event.clone() {
+                PendingEvent::Sample(event) => commands.trigger(event),
+                PendingEvent::Layered(event) => commands.trigger(event),
+            }
+        }
+
+        waiting
+    });
+
+    let finished: Vec<_> = loader
+        .announcing
+        .iter()
+        .copied()
+        .filter(|group| loader.group_loaded(group, &server))
+        .collect();
+
+    for group in finished {
+        loader.announcing.remove(group);
+        commands.trigger(SampleGroupLoaded { group });
+    }
+}
+
+/// Load or unload a preload group.
+#[derive(Event, Debug, Clone)]
+pub enum SampleGroupEvent {
+    Load(&'static str),
+    /// Release the group's samples, unless another loaded group shares them.
+    ///
+    /// Voices that are already playing keep their sample until they finish.
+    Unload(&'static str),
+}
+
+/// Triggered once every sample in a loaded group is ready, or has failed.
+#[derive(Event, Debug, Clone)]
+pub struct SampleGroupLoaded {
+    pub group: &'static str,
+}
+
+fn observe_group_event(
+    trigger: Trigger<SampleGroupEvent>,
+    mut loader: ResMut<SampleLoader>,
+    server: Res<AssetServer>,
+) -> Result {
+    match *trigger {
+        SampleGroupEvent::Load(group) => {
+            let samples = *loader
+                .groups
+                .get(group)
+                .ok_or_else(|| format!("failed to find sample group \"{group}\""))?;
+
+            loader.loaded_groups.insert(group);
+            loader.announcing.insert(group);
+            for sample in samples {
+                loader.load(sample, &server);
+            }
+        }
+        SampleGroupEvent::Unload(group) => {
+            if !loader.loaded_groups.remove(group) {
+                return Ok(());
+            }
+            loader.announcing.remove(group);
+
+            let Some(samples) = loader.groups.get(group).copied() else {
+                return Ok(());
+            };
+
+            let still_needed: HashSet<&str> = loader
+                .loaded_groups
+                .iter()
+                .filter_map(|group| loader.groups.get(group).copied())
+                .flat_map(|samples| samples.iter().copied())
+                .collect();
+
+            // Dropping the last handle releases the sample.
+            for sample in samples {
+                if !still_needed.contains(sample) {
+                    loader.handles.remove(sample);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The total length of a set of sample lists, for sizing [`concat_samples`].
+pub const fn count_samples(lists: &[&[&'static str]]) -> usize {
+    let mut count = 0;
+    let mut i = 0;
+    while i < lists.len() {
+        count += lists[i].len();
+        i += 1;
+    }
+
+    count
+}
+
+/// Join sample lists at compile time, so a group can be built from the
+/// lists its samples are defined in.
+///
+/// `N` must be the [`count_samples`] of `lists`.
+pub const fn concat_samples<const N: usize>(lists: &[&[&'static str]]) -> [&'static str; N] {
+    let mut samples = [""; N];
+    let mut index = 0;
+
+    let mut i = 0;
+    while i < lists.len() {
+        let mut j = 0;
+        while j < lists[i].len() {
+            samples[index] = lists[i][j];
+            index += 1;
+            j += 1;
+        }
+        i += 1;
+    }
+
+    samples
+}
+
+pub trait RegisterSampleGroup {
+    /// Make a set of samples loadable together with a [`SampleGroupEvent`].
+    fn register_sample_group(
+        &mut self,
+        name: &'static str,
+        samples: &'static [&'static str],
+    ) -> &mut Self;
+
+    /// Note samples that are played without a group, so strict mode can check them too.
+    fn reference_samples(&mut self, samples: impl IntoIterator<Item = &'static str>) -> &mut Self;
+}
+
+impl RegisterSampleGroup for App {
+    fn register_sample_group(
+        &mut self,
+        name: &'static str,
+        samples: &'static [&'static str],
+    ) -> &mut Self {
+        self.init_resource::<SampleLoader>();
+        self.world_mut()
+            .resource_mut::<SampleLoader>()
+            .groups
+            .insert(name, samples);
+
+        self
+    }
+
+    fn reference_samples(&mut self, samples: impl IntoIterator<Item = &'static str>) -> &mut Self {
+        self.init_resource::<SampleLoader>();
+        self.world_mut()
+            .resource_mut::<SampleLoader>()
+            .reference(samples);
+
+        self
+    }
+}
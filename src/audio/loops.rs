@@ -0,0 +1,506 @@
+//! Loop regions, for samples with an intro and outro.
+//!
+//! A sample may describe the region that should repeat while it
+//! loops, either with a sidecar file next to it or with the loop
+//! in a WAV file's `smpl` chunk. Looping voices play the intro once,
+//! repeat the region until a [`LoopExitEvent`] arrives, and then
+//! finish the current pass before playing the outro.
+//!
+//! Sidecar files share the sample's name with `.loop` appended,
+//! like `aster.ogg.loop`, and give the region in seconds:
+//!
+//! ```text
+//! start = 4.8
+//! end = 43.2
+//! ```
+//!
+//! Leaving out `end` loops to the end of the file.
+
+use bevy::prelude::*;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+pub fn loops_plugin(app: &mut App) {
+    app.add_observer(observe_loop_exit);
+}
+
+/// The repeating part of a sample, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoopRegion {
+    pub start: f64,
+    /// `None` loops to the end of the sample.
+    pub end: Option<f64>,
+}
+
+/// A [`LoopRegion`] resolved to frames at a particular sample rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopFrames {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl LoopRegion {
+    /// Resolve the region for a sample of `len` frames, or `None` if it's empty.
+    pub fn frames(&self, sample_rate: u32, len: u64) -> Option<LoopFrames> {
+        let to_frames = |seconds: f64| ((seconds * sample_rate as f64).round() as u64).min(len);
+
+        let start = to_frames(self.start.max(0.0));
+        let end = self.end.map(to_frames).unwrap_or(len);
+
+        (start < end).then_some(LoopFrames { start, end })
+    }
+}
+
+/// Find a sample's loop region from its contents and its sidecar file, if it has one.
+///
+/// A sidecar takes precedence over an embedded `smpl` chunk.
+/// Malformed sidecars are an error, so they can be reported.
+pub fn find_loop_region(
+    sample: &[u8],
+    sidecar: Option<&str>,
+) -> Result<Option<LoopRegion>, MalformedLoopFile> {
+    match sidecar {
+        Some(contents) => parse_sidecar(contents).map(Some).ok_or(MalformedLoopFile),
+        None => Ok(parse_smpl_chunk(sample)),
+    }
+}
+
+/// A loop sidecar file that couldn't be parsed.
+#[derive(Debug, Clone, Copy)]
+pub struct MalformedLoopFile;
+
+fn parse_sidecar(contents: &str) -> Option<LoopRegion> {
+    let mut start = None;
+    let mut end = None;
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = line.split_once('=')?;
+        let value: f64 = value.trim().parse().ok()?;
+
+        match key.trim() {
+            "start" => start = Some(value),
+            "end" => end = Some(value),
+            _ => return None,
+        }
+    }
+
+    Some(LoopRegion {
+        start: start.unwrap_or(0.0),
+        end,
+    })
+}
+
+/// Read the first loop from a RIFF WAVE file's `smpl` chunk.
+fn parse_smpl_chunk(bytes: &[u8]) -> Option<LoopRegion> {
+    let u32_at = |offset: usize| -> Option<u32> {
+        let bytes = bytes.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    };
+
+    if bytes.get(0..4)? != b"RIFF" || bytes.get(8..12)? != b"WAVE" {
+        return None;
+    }
+
+    let mut sample_rate = None;
+    let mut frames = None;
+
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = u32_at(offset + 4)? as usize;
+        let body = offset + 8;
+
+        match id {
+            b"fmt " => sample_rate = Some(u32_at(body + 4)?),
+            b"smpl" if u32_at(body + 28)? > 0 => {
+                // The first loop follows the 36-byte header. Its end is inclusive.
+                let start = u32_at(body + 36 + 8)?;
+                let end = u32_at(body + 36 + 12)?;
+
+                frames = Some((start, end + 1));
+            }
+            _ => {}
+        }
+
+        // Chunks are padded to an even length.
+        offset = body + size + (size & 1);
+    }
+
+    let sample_rate = sample_rate.filter(|r| *r > 0)? as f64;
+    let (start, end) = frames?;
+
+    Some(LoopRegion {
+        start: start as f64 / sample_rate,
+        end: Some(end as f64 / sample_rate),
+    })
+}
+
+/// Leave the loop of a named voice and play its outro.
+///
+/// The current pass through the loop finishes first, so the
+/// outro lines up the same way it does in the source file.
+#[derive(Event, Debug, Clone)]
+pub struct LoopExitEvent {
+    /// The name of the sample handle to target.
+    pub name: &'static str,
+}
+
+/// Shared state between a looping voice and the audio thread.
+///
+/// Engines insert this on voices that play a [`LoopRegion`].
+#[derive(Component, Debug, Default, Clone)]
+pub struct LoopHandle {
+    exit: Arc<AtomicBool>,
+    finished: Arc<AtomicBool>,
+}
+
+impl LoopHandle {
+    pub fn exit(&self) {
+        self.exit.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` once the outro has finished playing.
+    pub fn finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
+
+fn observe_loop_exit(
+    trigger: Trigger<LoopExitEvent>,
+    handles: Query<(&Name, &LoopHandle)>,
+) -> Result {
+    let event_name = Name::new(trigger.name);
+
+    for (name, handle) in &handles {
+        if name == &event_name {
+            handle.exit();
+
+            return Ok(());
+        }
+    }
+
+    Err(format!("failed to find looping audio handle for name \"{event_name}\"").into())
+}
+
+/// Maps a voice's ever-increasing playhead onto the frames of a looped sample.
+///
+/// This is called from the audio thread, so it only touches atomics.
+#[derive(Debug)]
+pub struct LoopPlayhead {
+    frames: LoopFrames,
+    len: u64,
+    exit: Arc<AtomicBool>,
+    finished: Arc<AtomicBool>,
+    /// The playhead position where the outro begins, once known.
+    exit_at: AtomicU64,
+}
+
+impl LoopPlayhead {
+    pub fn new(frames: LoopFrames, len: u64, handle: &LoopHandle) -> Self {
+        Self {
+            frames,
+            len,
+            exit: handle.exit.clone(),
+            finished: handle.finished.clone(),
+            exit_at: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    /// The sample frame to play at `position`, or `None` once the outro is over.
+    ///
+    /// Positions must be requested in order, since the exit point
+    /// is fixed the first time it's seen.
+    pub fn source_frame(&self, position: u64) -> Option<u64> {
+        let LoopFrames { start, end } = self.frames;
+        let length = end - start;
+
+        let mut exit_at = self.exit_at.load(Ordering::Relaxed);
+        if exit_at == u64::MAX && self.exit.load(Ordering::Relaxed) {
+            // The next loop boundary at or after this position.
+            exit_at = if position <= end {
+                end
+            } else {
+                end + (position - end).div_ceil(length) * length
+            };
+
+            self.exit_at.store(exit_at, Ordering::Relaxed);
+        }
+
+        if position >= exit_at {
+            let frame = end + (position - exit_at);
+            if frame >= self.len {
+                self.finished.store(true, Ordering::Relaxed);
+                return None;
+            }
+
+            return Some(frame);
+        }
+
+        if position < end {
+            Some(position)
+        } else {
+            Some(start + (position - start) % length)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mono 16-bit WAVE file of silence, with the given chunks after its data.
+    fn wave(sample_rate: u32, frames: u32, chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&sample_rate.to_le_bytes());
+        fmt.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        fmt.extend_from_slice(&2u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+
+        let data = vec![0; frames as usize * 2];
+
+        for (id, chunk) in [(b"fmt ", fmt), (b"data", data)]
+            .into_iter()
+            .chain(chunks.iter().cloned())
+        {
+            body.extend_from_slice(id);
+            body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            body.extend_from_slice(&chunk);
+            if chunk.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+
+        bytes
+    }
+
+    /// A `smpl` chunk with a single forward loop, whose end frame is inclusive.
+    fn smpl(start: u32, end: u32) -> Vec<u8> {
+        let mut chunk = vec![0; 36];
+        chunk[28..32].copy_from_slice(&1u32.to_le_bytes());
+
+        for value in [0, 0, start, end, 0, 0] {
+            chunk.extend_from_slice(&value.to_le_bytes());
+        }
+
+        chunk
+    }
+
+    #[test]
+    fn smpl_loop_ends_are_inclusive() {
+        let bytes = wave(100, 300, &[(b"smpl", smpl(100, 199))]);
+        let region = parse_smpl_chunk(&bytes).unwrap();
+
+        assert_eq!(
+            region,
+            LoopRegion {
+                start: 1.0,
+                end: Some(2.0)
+            }
+        );
+        assert_eq!(
+            region.frames(100, 300),
+            Some(LoopFrames {
+                start: 100,
+                end: 200
+            })
+        );
+    }
+
+    #[test]
+    fn smpl_chunks_follow_odd_length_chunks() {
+        let bytes = wave(
+            100,
+            300,
+            &[(b"LIST", vec![1, 2, 3]), (b"smpl", smpl(10, 19))],
+        );
+
+        assert_eq!(
+            parse_smpl_chunk(&bytes),
+            Some(LoopRegion {
+                start: 0.1,
+                end: Some(0.2)
+            })
+        );
+    }
+
+    #[test]
+    fn missing_or_malformed_smpl_chunks_have_no_loop() {
+        // No `smpl` chunk at all.
+        assert_eq!(parse_smpl_chunk(&wave(100, 300, &[])), None);
+
+        // A `smpl` chunk without any loops.
+        let mut empty = smpl(0, 10);
+        empty[28..32].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(parse_smpl_chunk(&wave(100, 300, &[(b"smpl", empty)])), None);
+
+        // A `smpl` chunk cut off before its loop.
+        let mut truncated = wave(100, 300, &[(b"smpl", smpl(0, 10))]);
+        truncated.truncate(truncated.len() - 16);
+        assert_eq!(parse_smpl_chunk(&truncated), None);
+
+        // A format with no sample rate.
+        assert_eq!(
+            parse_smpl_chunk(&wave(0, 300, &[(b"smpl", smpl(0, 10))])),
+            None
+        );
+
+        // Something that isn't a WAVE file.
+        assert_eq!(parse_smpl_chunk(b"OggS and then some"), None);
+        assert_eq!(parse_smpl_chunk(&[]), None);
+    }
+
+    #[test]
+    fn out_of_range_loops_are_clamped_or_dropped() {
+        // A loop running past the end of the sample stops at its end.
+        let bytes = wave(100, 300, &[(b"smpl", smpl(250, 999))]);
+        let region = parse_smpl_chunk(&bytes).unwrap();
+        assert_eq!(
+            region.frames(100, 300),
+            Some(LoopFrames {
+                start: 250,
+                end: 300
+            })
+        );
+
+        // Loops that start after they end, or past the end of the sample, are empty.
+        let backwards = parse_smpl_chunk(&wave(100, 300, &[(b"smpl", smpl(200, 100))])).unwrap();
+        assert_eq!(backwards.frames(100, 300), None);
+
+        let past_end = parse_smpl_chunk(&wave(100, 300, &[(b"smpl", smpl(400, 499))])).unwrap();
+        assert_eq!(past_end.frames(100, 300), None);
+    }
+
+    #[test]
+    fn sidecars_give_the_region_in_seconds() {
+        assert_eq!(
+            parse_sidecar("start = 4.8\nend = 43.2\n"),
+            Some(LoopRegion {
+                start: 4.8,
+                end: Some(43.2)
+            })
+        );
+
+        assert_eq!(
+            parse_sidecar("# the intro is a bar long\n\n  start=2  \n"),
+            Some(LoopRegion {
+                start: 2.0,
+                end: None
+            })
+        );
+
+        assert_eq!(
+            parse_sidecar(""),
+            Some(LoopRegion {
+                start: 0.0,
+                end: None
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_sidecars_are_rejected() {
+        for contents in [
+            "start 4.8",
+            "start = soon",
+            "begin = 4.8",
+            "start = 1.0\nend =",
+        ] {
+            assert_eq!(parse_sidecar(contents), None, "{contents:?}");
+        }
+
+        assert!(find_loop_region(&[], Some("start = soon")).is_err());
+    }
+
+    #[test]
+    fn sidecars_take_precedence_over_smpl_chunks() {
+        let bytes = wave(100, 300, &[(b"smpl", smpl(100, 199))]);
+
+        assert_eq!(
+            find_loop_region(&bytes, Some("start = 0.5")).unwrap(),
+            Some(LoopRegion {
+                start: 0.5,
+                end: None
+            })
+        );
+        assert_eq!(
+            find_loop_region(&bytes, None).unwrap(),
+            Some(LoopRegion {
+                start: 1.0,
+                end: Some(2.0)
+            })
+        );
+    }
+
+    /// A ten-frame intro, a ten-frame loop, and a ten-frame outro.
+    fn playhead() -> (LoopPlayhead, LoopHandle) {
+        let handle = LoopHandle::default();
+        let playhead = LoopPlayhead::new(LoopFrames { start: 10, end: 20 }, 30, &handle);
+
+        (playhead, handle)
+    }
+
+    #[test]
+    fn intros_play_through_unchanged() {
+        let (playhead, _) = playhead();
+
+        for position in 0..20 {
+            assert_eq!(playhead.source_frame(position), Some(position));
+        }
+    }
+
+    #[test]
+    fn loops_wrap_to_their_start_at_their_end() {
+        let (playhead, _) = playhead();
+
+        assert_eq!(playhead.source_frame(19), Some(19));
+        assert_eq!(playhead.source_frame(20), Some(10));
+        assert_eq!(playhead.source_frame(29), Some(19));
+        assert_eq!(playhead.source_frame(30), Some(10));
+        assert_eq!(playhead.source_frame(1005), Some(15));
+    }
+
+    #[test]
+    fn exits_finish_the_pass_before_the_outro() {
+        let (playhead, handle) = playhead();
+
+        assert_eq!(playhead.source_frame(25), Some(15));
+        handle.exit();
+
+        assert_eq!(playhead.source_frame(26), Some(16));
+        assert_eq!(playhead.source_frame(29), Some(19));
+        assert_eq!(playhead.source_frame(30), Some(20));
+        assert_eq!(playhead.source_frame(39), Some(29));
+        assert!(!handle.finished());
+
+        assert_eq!(playhead.source_frame(40), None);
+        assert!(handle.finished());
+    }
+
+    #[test]
+    fn exits_during_the_intro_skip_the_loop() {
+        let (playhead, handle) = playhead();
+
+        handle.exit();
+
+        assert_eq!(playhead.source_frame(5), Some(5));
+        assert_eq!(playhead.source_frame(19), Some(19));
+        assert_eq!(playhead.source_frame(20), Some(20));
+        assert_eq!(playhead.source_frame(29), Some(29));
+        assert_eq!(playhead.source_frame(30), None);
+    }
+}
@@ -0,0 +1,374 @@
+//! Loudness normalization.
+//!
+//! Samples are mastered at different levels, so a volume that sounds
+//! right for one is wrong for the next. With a target set, each sample's
+//! integrated loudness is measured as it loads, following EBU R128, and
+//! the sample is given the gain that brings it to the target. Engines
+//! apply that gain underneath the event's `volume`.
+//!
+//! Gains can be set by hand in `assets/loudness.ron`, in decibels,
+//! which takes the place of measuring:
+//!
+//! ```ron
+//! {
+//!     "talk.wav": -3.0,
+//! }
+//! ```
+
+use bevy::{
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+};
+use std::{
+    collections::VecDeque,
+    f64::consts::PI,
+    sync::{Arc, Mutex},
+};
+
+pub fn loudness_plugin(app: &mut App) {
+    app.init_resource::<Normalization>();
+
+    let overrides = read_overrides();
+    app.world_mut().resource_mut::<Normalization>().overrides = Arc::new(overrides);
+}
+
+/// The most gain normalization will apply, in decibels, either way.
+const MAX_GAIN_DB: f32 = 24.0;
+
+/// How samples are normalized.
+///
+/// Engines copy this into their sample loader, so it should be
+/// inserted before they're added.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct Normalization {
+    /// The integrated loudness to bring samples to, in LUFS.
+    ///
+    /// `None` leaves samples as they were mastered, apart from overrides.
+    pub target: Option<f32>,
+    /// Gains set by hand, in decibels, by sample path.
+    overrides: Arc<HashMap<String, f32>>,
+    /// Loudness already measured, by sample path.
+    measured: Arc<Mutex<HashMap<String, Option<f32>>>>,
+    /// Samples being measured in the background right now.
+    measuring: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Normalization {
+    pub fn with_target(target: Option<f32>) -> Self {
+        Self {
+            target,
+            ..Default::default()
+        }
+    }
+
+    /// Whether a sample's loudness needs to be measured.
+    pub fn measures(&self, sample: &str) -> bool {
+        self.target.is_some() && !self.overrides.contains_key(sample)
+    }
+
+    /// The linear gain for a sample, given its measured loudness.
+    pub fn gain(&self, sample: &str, loudness: Option<f32>) -> f32 {
+        if let Some(db) = self.overrides.get(sample) {
+            return db_to_amplitude(*db);
+        }
+
+        match (self.target, loudness) {
+            (Some(target), Some(loudness)) => {
+                db_to_amplitude((target - loudness).clamp(-MAX_GAIN_DB, MAX_GAIN_DB))
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// The loudness of a sample, if it's been measured.
+    ///
+    /// The inner `None` means the sample was measured, but is silent.
+    pub fn measured(&self, sample: &str) -> Option<Option<f32>> {
+        self.measured.lock().unwrap().get(sample).copied()
+    }
+
+    /// Measure a sample's loudness on a background thread.
+    ///
+    /// Nothing happens if the sample is already measured, or
+    /// is being measured, so each file is only decoded once.
+    pub fn measure_in_background(
+        &self,
+        sample: &str,
+        measure: impl FnOnce() -> Option<f32> + Send + 'static,
+    ) {
+        if self.measured(sample).is_some()
+            || !self.measuring.lock().unwrap().insert(sample.to_owned())
+        {
+            return;
+        }
+
+        let normalization = self.clone();
+        let thread_sample = sample.to_owned();
+        let spawned = std::thread::Builder::new()
+            .name(format!("measure {sample}"))
+            .spawn(move || {
+                normalization.remember(&thread_sample, measure());
+                normalization
+                    .measuring
+                    .lock()
+                    .unwrap()
+                    .remove(&thread_sample);
+            });
+
+        if let Err(e) = spawned {
+            warn!("failed to measure {sample}: {e}");
+            self.measuring.lock().unwrap().remove(sample);
+        }
+    }
+
+    /// Store a sample's loudness, replacing any earlier measurement.
+    pub fn remember(&self, sample: &str, loudness: Option<f32>) {
+        self.measured
+            .lock()
+            .unwrap()
+            .insert(sample.to_owned(), loudness);
+    }
+}
+
+fn db_to_amplitude(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+fn read_overrides() -> HashMap<String, f32> {
+    let path = std::path::Path::new("assets/loudness.ron");
+    if !path.exists() {
+        return HashMap::default();
+    }
+
+    let overrides = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| {
+            ron::from_str::<HashMap<String, f32>>(&contents).map_err(|e| e.to_string())
+        });
+
+    overrides.unwrap_or_else(|e| {
+        warn!("failed to load loudness overrides: {e}");
+        HashMap::default()
+    })
+}
+
+/// Measure the integrated loudness of a decoded sample, in LUFS.
+///
+/// Silent samples have no loudness.
+pub fn measure(channels: &[Vec<f32>], sample_rate: u32) -> Option<f32> {
+    let mut meter = LoudnessMeter::new(channels.len(), sample_rate);
+    let mut frame = vec![0.0; channels.len()];
+
+    for index in 0..channels.first().map(Vec::len).unwrap_or(0) {
+        for (sample, channel) in frame.iter_mut().zip(channels) {
+            *sample = channel[index];
+        }
+        meter.push(&frame);
+    }
+
+    meter.finish()
+}
+
+/// A second-order filter section.
+#[derive(Debug, Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+
+        y
+    }
+}
+
+/// The K-weighting filter from ITU-R BS.1770, designed for any sample rate.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    // A high shelf that models the head's acoustic effect.
+    let k = (PI * 1681.974450955533 / rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+
+    // A high pass that discounts the lowest frequencies.
+    let k = (PI * 38.13547087602444 / rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+
+    [shelf, high_pass]
+}
+
+/// Measures integrated loudness one frame at a time.
+///
+/// Power is gathered in 100ms steps, and every four steps make a 400ms
+/// block, so blocks overlap by 75%. Quiet blocks are gated out before
+/// averaging, just as BS.1770 describes.
+pub struct LoudnessMeter {
+    filters: Vec<[Biquad; 2]>,
+    step_frames: usize,
+    step_filled: usize,
+    step_power: f64,
+    steps: VecDeque<f64>,
+    blocks: Vec<f64>,
+    /// Power across every frame, for samples shorter than a block.
+    total_power: f64,
+    total_frames: usize,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        Self {
+            filters: vec![k_weighting(sample_rate); channels],
+            step_frames: (sample_rate as usize / 10).max(1),
+            step_filled: 0,
+            step_power: 0.0,
+            steps: VecDeque::with_capacity(4),
+            blocks: Vec::new(),
+            total_power: 0.0,
+            total_frames: 0,
+        }
+    }
+
+    /// Add one frame, with a sample per channel.
+    pub fn push(&mut self, frame: &[f32]) {
+        let mut power = 0.0;
+        for (filters, sample) in self.filters.iter_mut().zip(frame) {
+            let filtered = filters
+                .iter_mut()
+                .fold(*sample as f64, |x, filter| filter.process(x));
+
+            power += filtered * filtered;
+        }
+
+        self.step_power += power;
+        self.total_power += power;
+        self.total_frames += 1;
+
+        self.step_filled += 1;
+        if self.step_filled < self.step_frames {
+            return;
+        }
+
+        if self.steps.len() == 4 {
+            self.steps.pop_front();
+        }
+        self.steps
+            .push_back(self.step_power / self.step_frames as f64);
+        self.step_filled = 0;
+        self.step_power = 0.0;
+
+        if self.steps.len() == 4 {
+            self.blocks.push(self.steps.iter().sum::<f64>() / 4.0);
+        }
+    }
+
+    /// Add any number of interleaved frames.
+    pub fn push_interleaved(&mut self, samples: &[f32]) {
+        let channels = self.filters.len().max(1);
+        for frame in samples.chunks_exact(channels) {
+            self.push(frame);
+        }
+    }
+
+    /// The integrated loudness of everything pushed, in LUFS.
+    pub fn finish(&self) -> Option<f32> {
+        let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+        let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+
+        // A sample shorter than a block is measured as a whole.
+        let blocks = if self.blocks.is_empty() && self.total_frames > 0 {
+            vec![self.total_power / self.total_frames as f64]
+        } else {
+            self.blocks.clone()
+        };
+
+        let audible: Vec<f64> = blocks
+            .into_iter()
+            .filter(|power| loudness(*power) > -70.0)
+            .collect();
+
+        if audible.is_empty() {
+            return None;
+        }
+
+        let relative_gate = loudness(mean(&audible)) - 10.0;
+        let gated: Vec<f64> = audible
+            .into_iter()
+            .filter(|power| loudness(*power) > relative_gate)
+            .collect();
+
+        Some(loudness(mean(&gated)) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::TAU;
+
+    const RATE: u32 = 48_000;
+
+    fn sine(frequency: f32, amplitude: f32, seconds: f32) -> Vec<f32> {
+        (0..(seconds * RATE as f32) as usize)
+            .map(|i| amplitude * (TAU * frequency * i as f32 / RATE as f32).sin())
+            .collect()
+    }
+
+    // BS.1770 is calibrated so a full-scale 997 Hz sine in one channel reads -3.01 LUFS.
+    #[test]
+    fn full_scale_sine_matches_reference() {
+        let loudness = measure(&[sine(997.0, 1.0, 5.0)], RATE).unwrap();
+
+        assert!((loudness - -3.01).abs() < 0.05, "measured {loudness} LUFS");
+    }
+
+    #[test]
+    fn loudness_follows_level() {
+        let loudness = measure(&[sine(997.0, 0.1, 5.0)], RATE).unwrap();
+
+        assert!((loudness - -23.01).abs() < 0.05, "measured {loudness} LUFS");
+    }
+
+    #[test]
+    fn silence_is_gated_out() {
+        let mut channel = vec![0.0; 5 * RATE as usize];
+        channel.extend(sine(997.0, 1.0, 5.0));
+
+        let loudness = measure(&[channel], RATE).unwrap();
+
+        // Only the few blocks straddling the start are partly silent. Without
+        // gating, the silence would bring this down by about 3 dB.
+        assert!((loudness - -3.01).abs() < 0.2, "measured {loudness} LUFS");
+    }
+
+    #[test]
+    fn silent_samples_have_no_loudness() {
+        assert_eq!(measure(&[vec![0.0; RATE as usize]], RATE), None);
+    }
+}
@@ -0,0 +1,319 @@
+//! Standard MIDI file playback through [`AudioEvent`]s.
+//!
+//! Every `.mid` file under `assets` is flattened into a list of timed
+//! notes at startup. A [`MidiEvent`] plays one of them with an [`Instrument`],
+//! which maps each note to the nearest sample and pitches it with `speed`.
+//! Notes are scheduled on the [`AudioClock`], just like a [`SoundRepeater`][crate::audio::repeater::SoundRepeater].
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use midly::{Format, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::sync::Arc;
+use walkdir::WalkDir;
+
+use crate::audio::{
+    AudioEvent,
+    clock::{AudioClock, AudioInstant},
+};
+
+pub fn midi_plugin(app: &mut App) {
+    app.add_systems(PreStartup, load_midi_files)
+        .add_systems(Update, play_sequences)
+        .add_observer(observe_midi_event);
+}
+
+/// A single note-on, flattened out of a MIDI file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MidiNote {
+    /// The time from the start of the file, in seconds.
+    pub time: f64,
+    pub channel: u8,
+    pub key: u8,
+    pub velocity: u8,
+}
+
+/// Every MIDI file found in `assets`, by path.
+#[derive(Resource, Default)]
+pub struct MidiFiles(HashMap<String, Arc<[MidiNote]>>);
+
+impl MidiFiles {
+    pub fn get(&self, path: &str) -> Option<&Arc<[MidiNote]>> {
+        self.0.get(path)
+    }
+}
+
+fn load_midi_files(mut commands: Commands) {
+    let assets_path = std::path::Path::new("assets");
+
+    let mut files = HashMap::default();
+    for entry in WalkDir::new(assets_path).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.extension().is_none_or(|e| e != "mid") {
+            continue;
+        }
+
+        let name: String = path
+            .strip_prefix(assets_path)
+            .unwrap()
+            .to_string_lossy()
+            .into();
+
+        let notes = std::fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| read_notes(&bytes));
+
+        match notes {
+            Ok(notes) => {
+                files.insert(name, notes.into());
+            }
+            Err(e) => warn!("failed to load MIDI file {}: {e}", path.display()),
+        }
+    }
+
+    commands.insert_resource(MidiFiles(files));
+}
+
+/// The default tempo of a MIDI file, in microseconds per beat.
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Parse a MIDI file's note-ons, in order, with times in seconds.
+pub fn read_notes(bytes: &[u8]) -> Result<Vec<MidiNote>, String> {
+    let smf = Smf::parse(bytes).map_err(|e| e.to_string())?;
+
+    // Gather every track's events on a shared timeline of ticks.
+    // Sequential files play their tracks one after another.
+    let mut events = Vec::new();
+    let mut track_start = 0u64;
+    for track in &smf.tracks {
+        let mut tick = track_start;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            events.push((tick, event.kind));
+        }
+
+        if smf.header.format == Format::Sequential {
+            track_start = tick;
+        }
+    }
+    events.sort_by_key(|(tick, _)| *tick);
+
+    let seconds_per_tick = |tempo: u32| match smf.header.timing {
+        Timing::Metrical(ticks_per_beat) => {
+            tempo as f64 / 1_000_000.0 / ticks_per_beat.as_int().max(1) as f64
+        }
+        Timing::Timecode(fps, ticks_per_frame) => {
+            1.0 / (fps.as_f32() as f64 * ticks_per_frame.max(1) as f64)
+        }
+    };
+
+    let mut notes = Vec::new();
+    let mut tick_length = seconds_per_tick(DEFAULT_TEMPO);
+    let mut last_tick = 0;
+    let mut time = 0.0;
+
+    for (tick, kind) in events {
+        time += (tick - last_tick) as f64 * tick_length;
+        last_tick = tick;
+
+        match kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                tick_length = seconds_per_tick(tempo.as_int());
+            }
+            // A note-on with no velocity is really a note-off.
+            TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOn { key, vel },
+            } if vel.as_int() > 0 => notes.push(MidiNote {
+                time,
+                channel: channel.as_int(),
+                key: key.as_int(),
+                velocity: vel.as_int(),
+            }),
+            _ => {}
+        }
+    }
+
+    Ok(notes)
+}
+
+/// Maps MIDI notes to samples.
+#[derive(Debug, Clone, Copy)]
+pub struct Instrument {
+    /// Each sample along with the MIDI note it sounds at.
+    pub samples: &'static [(u8, &'static str)],
+    /// Only play notes on this channel, counting from zero.
+    pub channel: Option<u8>,
+}
+
+impl Instrument {
+    /// The wind chimes, rooted by their file names.
+    pub const CHIMES: Instrument = Instrument {
+        samples: &[
+            (74, "chimes/chime-d1.ogg"),
+            (76, "chimes/chime-e1.ogg"),
+            (77, "chimes/chime-f1.ogg"),
+            (81, "chimes/chime-a1.ogg"),
+            (83, "chimes/chime-b1.ogg"),
+            (86, "chimes/chime-d4.ogg"),
+        ],
+        channel: None,
+    };
+
+    /// The nearest sample to `key`, and the speed that pitches it there.
+    pub fn voice(&self, key: u8) -> Option<(&'static str, f32)> {
+        let (root, sample) = self
+            .samples
+            .iter()
+            .min_by_key(|(root, _)| root.abs_diff(key))?;
+
+        let semitones = key as f32 - *root as f32;
+
+        Some((*sample, 2f32.powf(semitones / 12.0)))
+    }
+}
+
+/// Play or stop a MIDI file.
+#[derive(Event, Debug, Clone)]
+pub enum MidiEvent {
+    Play(MidiSequence),
+    /// Stop scheduling notes for the named sequence.
+    ///
+    /// Notes that are already ringing play out.
+    Stop(&'static str),
+}
+
+/// A MIDI file played with an [`Instrument`].
+#[derive(Debug, Clone)]
+pub struct MidiSequence {
+    /// The file's path within `assets`.
+    pub file: &'static str,
+    pub instrument: Instrument,
+    pub volume: f32,
+    pub position: Option<Vec2>,
+    /// Multiplies the file's tempo without changing its pitch.
+    pub tempo: f32,
+    /// Given to the sequence and every note it plays.
+    pub name: Option<&'static str>,
+    /// When the file's first beat should land. `None` starts as soon as possible.
+    pub start: Option<AudioInstant>,
+}
+
+impl MidiSequence {
+    pub fn new(file: &'static str, instrument: Instrument) -> Self {
+        Self {
+            file,
+            instrument,
+            volume: 1.0,
+            position: None,
+            tempo: 1.0,
+            name: None,
+            start: None,
+        }
+    }
+
+    pub fn with_volume(mut self, volume: f32) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    pub fn at(mut self, position: Vec2) -> Self {
+        self.position = Some(position);
+        self
+    }
+
+    pub fn with_tempo(mut self, tempo: f32) -> Self {
+        self.tempo = tempo;
+        self
+    }
+
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+}
+
+#[derive(Component)]
+struct MidiPlayer {
+    sequence: MidiSequence,
+    notes: Arc<[MidiNote]>,
+    next: usize,
+}
+
+fn observe_midi_event(
+    trigger: Trigger<MidiEvent>,
+    files: Res<MidiFiles>,
+    players: Query<(Entity, &MidiPlayer)>,
+    mut commands: Commands,
+) -> Result {
+    match trigger.event() {
+        MidiEvent::Play(sequence) => {
+            let notes = files
+                .get(sequence.file)
+                .cloned()
+                .ok_or_else(|| format!("queued unknown MIDI file {}", sequence.file))?;
+
+            commands.spawn(MidiPlayer {
+                sequence: sequence.clone(),
+                notes,
+                next: 0,
+            });
+        }
+        MidiEvent::Stop(name) => {
+            for (entity, player) in &players {
+                if player.sequence.name == Some(*name) {
+                    commands.entity(entity).despawn();
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn play_sequences(
+    mut players: Query<(Entity, &mut MidiPlayer)>,
+    clock: Res<AudioClock>,
+    mut commands: Commands,
+) {
+    for (entity, mut player) in &mut players {
+        let start = *player.sequence.start.get_or_insert(clock.horizon());
+        let tempo = player.sequence.tempo.max(f32::EPSILON) as f64;
+
+        while let Some(note) = player.notes.get(player.next).copied() {
+            let at = AudioInstant(start.0 + note.time / tempo);
+            if at > clock.horizon() {
+                break;
+            }
+            player.next += 1;
+
+            let sequence = &player.sequence;
+            if sequence
+                .instrument
+                .channel
+                .is_some_and(|channel| channel != note.channel)
+            {
+                continue;
+            }
+
+            let Some((sample, speed)) = sequence.instrument.voice(note.key) else {
+                continue;
+            };
+
+            // Squaring the velocity sounds more even than a linear mapping.
+            let velocity = note.velocity as f32 / 127.0;
+
+            commands.trigger(AudioEvent {
+                sample,
+                position: sequence.position,
+                speed,
+                volume: sequence.volume * velocity * velocity,
+                name: sequence.name,
+                start: Some(at),
+                ..Default::default()
+            });
+        }
+
+        if player.next >= player.notes.len() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
@@ -6,20 +6,66 @@
 //! parameters that all engines in this demo can provide.
 //!
 //! The [`VolumeFadeEvent`] is also a bit special, as each engine needs
-//! to handle it differently.
+//! to handle it differently. The same goes for [`occlusion`], which
+//! asks each engine for a per-voice filter cutoff.
+//!
+//! Samples are [`sample::AudioSample`] assets, loaded on first use by the
+//! [`loading::SampleLoader`], so events for a sample that's still loading
+//! are briefly held back. Long samples are instead streamed from disk
+//! with a [`stream::SampleStream`].
 
 use bevy::prelude::*;
 use std::time::Duration;
 
+use clock::AudioInstant;
+
+pub mod ambience;
+pub mod ambisonics;
+pub mod cache;
 pub mod chimes;
+pub mod clock;
 pub mod footsteps;
+pub mod loading;
+pub mod loops;
+pub mod loudness;
+pub mod midi;
+pub mod modal;
+pub mod music;
+pub mod occlusion;
+pub mod panning;
+pub mod pools;
+pub mod reload;
 pub mod repeater;
+pub mod report;
+pub mod rhythm;
+pub mod sample;
+pub mod stems;
+pub mod stream;
 
 pub fn audio_plugin(app: &mut App) {
-    app.add_plugins(chimes::chimes_plugin)
+    app.add_plugins(ambience::ambience_plugin)
+        .add_plugins(ambisonics::ambisonics_plugin)
+        .add_plugins(cache::cache_plugin)
+        .add_plugins(chimes::chimes_plugin)
+        .add_plugins(clock::clock_plugin)
         .add_plugins(footsteps::footsteps_plugin)
+        .add_plugins(loading::loading_plugin)
+        .add_plugins(loops::loops_plugin)
+        .add_plugins(loudness::loudness_plugin)
+        .add_plugins(midi::midi_plugin)
+        .add_plugins(music::music_plugin)
+        .add_plugins(occlusion::occlusion_plugin)
+        .add_plugins(panning::panning_plugin)
+        .add_plugins(pools::pools_plugin)
+        .add_plugins(reload::reload_plugin)
+        .add_plugins(report::report_plugin)
         .add_plugins(repeater::repeater_plugin)
-        .add_observer(observe_fade_event);
+        .add_plugins(sample::sample_plugin)
+        .add_plugins(stems::stems_plugin)
+        .add_plugins(stream::stream_plugin)
+        .add_systems(Update, track_voice_volume)
+        .add_observer(observe_fade_event)
+        .add_observer(settle_voice_volume);
 }
 
 /// An event to queue playback.
@@ -29,23 +75,39 @@ pub fn audio_plugin(app: &mut App) {
 /// events, there won't be any meaningful latency introduced.
 #[derive(Debug, Event, Clone)]
 pub struct AudioEvent {
+    /// The sample's path within `assets`, or the name of a [`pools::SamplePool`].
     pub sample: &'static str,
+    /// A sample that's already loaded, played instead of `sample`.
+    pub handle: Option<Handle<sample::AudioSample>>,
     pub position: Option<Vec2>,
     pub speed: f32,
     pub volume: f32,
+    /// Repeat the sample, or its [`loops::LoopRegion`] if it has one.
     pub looping: bool,
     pub name: Option<&'static str>,
+    /// When to start playback on the [`clock::AudioClock`].
+    ///
+    /// `None` plays as soon as possible.
+    pub start: Option<AudioInstant>,
+    /// Decode the sample as a first-order AmbiX bed around the listener,
+    /// ignoring `position`.
+    ///
+    /// Only four-channel samples can be played this way.
+    pub ambisonic: bool,
 }
 
 impl Default for AudioEvent {
     fn default() -> Self {
         Self {
             sample: "",
+            handle: None,
             position: None,
             speed: 1.0,
             volume: 1.0,
             looping: false,
             name: None,
+            start: None,
+            ambisonic: false,
         }
     }
 }
@@ -61,6 +123,8 @@ pub struct VolumeFadeEvent {
     pub start: f32,
     pub end: f32,
     pub seconds: f32,
+    /// Stop the sound once the fade completes.
+    pub stop: bool,
 }
 
 impl Default for VolumeFadeEvent {
@@ -70,22 +134,69 @@ impl Default for VolumeFadeEvent {
             start: 0.0,
             end: 1.0,
             seconds: 1.0,
+            stop: false,
         }
     }
 }
 
+/// Change the playback speed of a named sound.
+#[derive(Event, Debug, Clone)]
+pub struct SpeedEvent {
+    /// The name of the sample handle to target.
+    pub name: &'static str,
+    pub speed: f32,
+}
+
 #[derive(Debug, Component)]
 pub struct VolumeFade {
     pub event: VolumeFadeEvent,
     pub timer: Timer,
 }
 
+impl VolumeFade {
+    /// Clean up a completed fade.
+    ///
+    /// Engines stop a voice when its entity is despawned.
+    pub fn finish(&self, entity: Entity, commands: &mut Commands) {
+        if self.event.stop {
+            commands.entity(entity).despawn();
+        } else {
+            commands.entity(entity).remove::<VolumeFade>();
+        }
+    }
+}
+
+/// A named voice's volume, following any fades.
+///
+/// Engines insert this along with a voice's [`Name`], so a voice can
+/// be faded out from wherever earlier fades left it.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct VoiceVolume(pub f32);
+
+fn track_voice_volume(mut voices: Query<(&mut VoiceVolume, &VolumeFade)>) {
+    for (mut volume, fade) in &mut voices {
+        volume.0 = fade.event.start.lerp(fade.event.end, fade.timer.fraction());
+    }
+}
+
+/// Fades are removed once they finish, which leaves the voice at the fade's end.
+fn settle_voice_volume(
+    trigger: Trigger<OnRemove, VolumeFade>,
+    mut voices: Query<(&mut VoiceVolume, &VolumeFade)>,
+) {
+    if let Ok((mut volume, fade)) = voices.get_mut(trigger.target()) {
+        volume.0 = fade.event.end;
+    }
+}
+
+/// Fade every voice with a matching name.
 fn observe_fade_event(
     trigger: Trigger<VolumeFadeEvent>,
     named_entities: Query<(Entity, &Name)>,
     mut commands: Commands,
 ) -> Result {
     let event_name = Name::new(trigger.name);
+    let mut found = false;
 
     for (entity, name) in &named_entities {
         if name == &event_name {
@@ -94,9 +205,15 @@ fn observe_fade_event(
                 event: trigger.clone(),
             });
 
-            return Ok(());
+            found = true;
         }
     }
 
-    Err(format!("failed to find matching audio handle for name \"{event_name}\"").into())
+    if !found {
+        return Err(
+            format!("failed to find matching audio handle for name \"{event_name}\"").into(),
+        );
+    }
+
+    Ok(())
 }
@@ -0,0 +1,133 @@
+//! Modal synthesis for chimes.
+//!
+//! A struck tube rings at a handful of inharmonic partials, each
+//! decaying on its own. Synthesizing them directly lets chimes play
+//! in any [`Scale`], rather than the key of a set of recordings.
+//!
+//! Engines render a [`ModalChime`] on the fly whenever a [`ChimeEvent`] is triggered.
+
+use bevy::prelude::*;
+use std::f64::consts::TAU;
+
+use crate::audio::AudioEvent;
+
+/// The frequency ratios of a free-free bar's first few modes, which tubular chimes follow closely.
+const PARTIAL_RATIOS: [f32; 5] = [1.0, 2.756, 5.404, 8.933, 13.345];
+const PARTIAL_AMPLITUDES: [f32; 5] = [1.0, 0.55, 0.3, 0.15, 0.08];
+
+/// The decay at which a partial is treated as silent, about -60 dB.
+const SILENT_DECAYS: f32 = 6.9;
+
+/// An event to synthesize a chime.
+///
+/// The `event` places the chime like any other sound, but its
+/// `sample` is ignored.
+#[derive(Event, Debug, Clone)]
+pub struct ChimeEvent {
+    pub chime: ModalChime,
+    pub event: AudioEvent,
+}
+
+/// A struck chime, described by its fundamental and how long it rings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModalChime {
+    /// The fundamental frequency in hertz.
+    pub frequency: f32,
+    /// The time constant of the fundamental's decay, in seconds.
+    ///
+    /// Higher partials die away proportionally faster.
+    pub decay: f32,
+}
+
+impl ModalChime {
+    pub fn new(frequency: f32) -> Self {
+        Self {
+            frequency,
+            decay: 1.2,
+        }
+    }
+
+    /// The number of frames until the chime falls silent.
+    pub fn len_frames(&self, sample_rate: u32) -> u64 {
+        (self.decay * SILENT_DECAYS * sample_rate as f32) as u64
+    }
+
+    /// The chime's output at `frame`.
+    ///
+    /// This is a pure function of time, so engines can render any part of it independently.
+    pub fn value_at(&self, frame: u64, sample_rate: u32) -> f32 {
+        // Time is kept in f64 so the phase stays accurate through long tails.
+        let t = frame as f64 / sample_rate as f64;
+        let nyquist = sample_rate as f64 / 2.0;
+
+        let mut value = 0.0;
+        for (k, (ratio, amplitude)) in PARTIAL_RATIOS.iter().zip(PARTIAL_AMPLITUDES).enumerate() {
+            let frequency = self.frequency as f64 * *ratio as f64;
+            if frequency >= nyquist {
+                break;
+            }
+
+            let decay = self.decay as f64 / (1.0 + k as f64);
+            value += amplitude as f64 * (-t / decay).exp() * (TAU * frequency * t).sin();
+        }
+
+        // Keep the peak near full scale, like the recorded chimes.
+        (value * 0.5) as f32
+    }
+}
+
+/// A musical scale, rooted at a particular pitch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scale {
+    /// The frequency of the first degree, in hertz.
+    pub root: f32,
+    /// Each degree's distance from the root in semitones, within one octave.
+    pub steps: &'static [i32],
+}
+
+impl Scale {
+    pub const MAJOR: &'static [i32] = &[0, 2, 4, 5, 7, 9, 11];
+    pub const MINOR: &'static [i32] = &[0, 2, 3, 5, 7, 8, 10];
+    pub const MAJOR_PENTATONIC: &'static [i32] = &[0, 2, 4, 7, 9];
+    pub const MINOR_PENTATONIC: &'static [i32] = &[0, 3, 5, 7, 10];
+
+    /// A scale rooted at a MIDI note, where 60 is middle C.
+    pub fn from_midi(note: u8, steps: &'static [i32]) -> Self {
+        Self {
+            root: midi_to_hz(note as f32),
+            steps,
+        }
+    }
+
+    /// The frequency of a scale degree, continuing into higher octaves.
+    pub fn frequency(&self, degree: usize) -> f32 {
+        let count = self.steps.len().max(1);
+        let octave = (degree / count) as i32;
+        let step = self.steps.get(degree % count).copied().unwrap_or(0);
+
+        self.root * 2f32.powf((octave * 12 + step) as f32 / 12.0)
+    }
+}
+
+pub fn midi_to_hz(note: f32) -> f32 {
+    440.0 * 2f32.powf((note - 69.0) / 12.0)
+}
+
+/// How the chimes are voiced, chosen with the `--chimes` flag.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq)]
+pub enum ChimeVoice {
+    /// The recorded chime samples.
+    #[default]
+    Sampled,
+    /// Synthesized chimes in the given scale.
+    ///
+    /// While music with a known key is playing, its key is used instead.
+    Synthesized(Scale),
+}
+
+impl ChimeVoice {
+    /// The scale synthesized chimes fall back to, a D major pentatonic scale.
+    pub fn synthesized() -> Self {
+        Self::Synthesized(Scale::from_midi(74, Scale::MAJOR_PENTATONIC))
+    }
+}
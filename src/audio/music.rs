@@ -0,0 +1,475 @@
+//! A small beat-aware music player.
+//!
+//! Tracks know their tempo and meter, so the player can report
+//! beats and bars as they happen and line up transitions with
+//! the next beat or bar. Transitions are scheduled on the
+//! [`AudioClock`], so the incoming track starts exactly on the
+//! boundary regardless of frame rate.
+
+use bevy::prelude::*;
+use std::time::Duration;
+
+use crate::audio::{
+    AudioEvent, SpeedEvent, VoiceVolume, VolumeFade, VolumeFadeEvent,
+    clock::{AudioClock, AudioInstant},
+    modal::Scale,
+    stems::{LayeredAudioEvent, MusicStem},
+};
+
+pub fn music_plugin(app: &mut App) {
+    app.init_resource::<MusicPlayer>()
+        .add_systems(Update, (schedule_transitions, emit_beats).chain())
+        .add_observer(observe_music_event)
+        .add_observer(observe_speed_event);
+}
+
+/// The name given to the playing music voice, for use with [`VolumeFadeEvent`].
+pub const MUSIC_NAME: &str = "music";
+
+/// The name given to a track while it's being replaced.
+const OUTGOING_NAME: &str = "music-outgoing";
+
+/// How long outgoing tracks take to fade out.
+const STOP_FADE_SECONDS: f32 = 0.05;
+
+/// A looping piece of music with a known tempo.
+#[derive(Debug, Clone, Copy)]
+pub struct MusicTrack {
+    pub sample: &'static str,
+    /// Synchronized layers that replace `sample` when present.
+    ///
+    /// The layers are mixed according to the [`MusicIntensity`][crate::audio::stems::MusicIntensity].
+    pub stems: &'static [MusicStem],
+    /// The tempo at a playback speed of 1.0.
+    pub bpm: f64,
+    pub beats_per_bar: u32,
+    /// The time from the start of the file to the first downbeat, in seconds.
+    pub offset: f64,
+    pub volume: f32,
+    pub speed: f32,
+    /// The track's key, so other sounds can play along.
+    ///
+    /// This is the key at a playback speed of 1.0.
+    pub key: Option<Scale>,
+}
+
+impl Default for MusicTrack {
+    fn default() -> Self {
+        Self {
+            sample: "",
+            stems: &[],
+            bpm: 120.0,
+            beats_per_bar: 4,
+            offset: 0.0,
+            volume: 1.0,
+            speed: 1.0,
+            key: None,
+        }
+    }
+}
+
+impl MusicTrack {
+    /// The length of a beat, accounting for playback speed.
+    pub fn beat_duration(&self) -> Duration {
+        Duration::from_secs_f64(60.0 / (self.bpm * self.speed as f64))
+    }
+
+    /// The key as heard, since playback speed transposes the track.
+    pub fn sounding_key(&self) -> Option<Scale> {
+        self.key.map(|key| Scale {
+            root: key.root * self.speed,
+            ..key
+        })
+    }
+}
+
+/// Where in the music a transition may happen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Quantize {
+    /// As soon as possible.
+    #[default]
+    Immediate,
+    Beat,
+    Bar,
+}
+
+#[derive(Debug, Clone)]
+pub enum MusicTransition {
+    /// Switch to another track.
+    Track(MusicTrack),
+    /// Play a one-shot over the current track.
+    Stinger(AudioEvent),
+    /// Fade the music out.
+    Stop,
+}
+
+/// Control the music player.
+#[derive(Event, Debug, Clone)]
+pub struct MusicEvent {
+    pub transition: MusicTransition,
+    pub quantize: Quantize,
+}
+
+impl MusicEvent {
+    /// Start a track immediately.
+    pub fn play(track: MusicTrack) -> Self {
+        Self {
+            transition: MusicTransition::Track(track),
+            quantize: Quantize::Immediate,
+        }
+    }
+}
+
+/// Triggered on every beat of the playing track.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct MusicBeat {
+    /// The number of beats since the track started.
+    pub index: u64,
+    pub bar: u64,
+    pub beat_in_bar: u32,
+}
+
+/// Triggered on the first beat of every bar.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct MusicBar {
+    pub index: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PlayingTrack {
+    track: MusicTrack,
+    /// When the first downbeat lands.
+    downbeat: AudioInstant,
+    /// The last beat that was reported.
+    last_beat: Option<u64>,
+}
+
+impl PlayingTrack {
+    /// The index of the last beat at or before `instant`.
+    fn beat_at(&self, instant: AudioInstant) -> Option<u64> {
+        if instant < self.downbeat {
+            return None;
+        }
+
+        let elapsed = instant.since(self.downbeat).as_secs_f64();
+        Some((elapsed / self.track.beat_duration().as_secs_f64()) as u64)
+    }
+
+    fn beat_instant(&self, beat: u64) -> AudioInstant {
+        AudioInstant(self.downbeat.0 + beat as f64 * self.track.beat_duration().as_secs_f64())
+    }
+
+    /// The first boundary at or after `earliest`.
+    fn next_boundary(&self, quantize: Quantize, earliest: AudioInstant) -> AudioInstant {
+        let step = match quantize {
+            Quantize::Immediate => return earliest,
+            Quantize::Beat => 1,
+            Quantize::Bar => self.track.beats_per_bar.max(1) as u64,
+        };
+
+        let beat = match self.beat_at(earliest) {
+            Some(beat) => beat.div_ceil(step) * step,
+            None => 0,
+        };
+
+        let mut boundary = self.beat_instant(beat);
+        if boundary < earliest {
+            boundary = self.beat_instant(beat + step);
+        }
+
+        boundary
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PendingTransition {
+    transition: MusicTransition,
+    at: AudioInstant,
+}
+
+#[derive(Resource, Default)]
+pub struct MusicPlayer {
+    playing: Option<PlayingTrack>,
+    pending: Option<PendingTransition>,
+    /// Replaced tracks, waiting to be faded out.
+    outgoing: Vec<OutgoingTrack>,
+}
+
+#[derive(Debug, Clone)]
+struct OutgoingTrack {
+    stop_at: AudioInstant,
+    voices: Vec<Entity>,
+}
+
+impl MusicPlayer {
+    pub fn current(&self) -> Option<&MusicTrack> {
+        self.playing.as_ref().map(|p| &p.track)
+    }
+}
+
+fn observe_music_event(
+    trigger: Trigger<MusicEvent>,
+    mut player: ResMut<MusicPlayer>,
+    clock: Res<AudioClock>,
+) {
+    let earliest = clock.horizon();
+    let at = match &player.playing {
+        Some(playing) => playing.next_boundary(trigger.quantize, earliest),
+        None => earliest,
+    };
+
+    // A newer request replaces anything that hasn't happened yet.
+    player.pending = Some(PendingTransition {
+        transition: trigger.transition.clone(),
+        at,
+    });
+}
+
+fn schedule_transitions(
+    mut player: ResMut<MusicPlayer>,
+    named: Query<(Entity, &Name)>,
+    volumes: Query<&VoiceVolume>,
+    clock: Res<AudioClock>,
+    mut commands: Commands,
+) {
+    player.outgoing.retain(|outgoing| {
+        if clock.now < outgoing.stop_at {
+            return true;
+        }
+
+        // Each voice fades from wherever earlier fades left it.
+        for &entity in &outgoing.voices {
+            let Ok(volume) = volumes.get(entity) else {
+                continue;
+            };
+
+            commands.entity(entity).insert(VolumeFade {
+                timer: Timer::new(Duration::from_secs_f32(STOP_FADE_SECONDS), TimerMode::Once),
+                event: VolumeFadeEvent {
+                    name: OUTGOING_NAME,
+                    start: volume.0,
+                    end: 0.0,
+                    seconds: STOP_FADE_SECONDS,
+                    stop: true,
+                },
+            });
+        }
+
+        false
+    });
+
+    let Some(pending) = player.pending.clone() else {
+        return;
+    };
+
+    if pending.at > clock.horizon() {
+        return;
+    }
+
+    player.pending = None;
+
+    match pending.transition {
+        MusicTransition::Stinger(mut event) => {
+            event.start = Some(pending.at);
+            commands.trigger(event);
+        }
+        MusicTransition::Track(track) => {
+            // Renaming the current track first means fades
+            // can't accidentally grab the wrong one.
+            retire_current(&mut player, pending.at, &named, &mut commands);
+
+            if track.stems.is_empty() {
+                commands.trigger(AudioEvent {
+                    sample: track.sample,
+                    volume: track.volume,
+                    speed: track.speed,
+                    looping: true,
+                    name: Some(MUSIC_NAME),
+                    start: Some(pending.at),
+                    ..Default::default()
+                });
+            } else {
+                commands.trigger(LayeredAudioEvent {
+                    stems: track.stems,
+                    volume: track.volume,
+                    speed: track.speed,
+                    looping: true,
+                    name: Some(MUSIC_NAME),
+                    start: Some(pending.at),
+                });
+            }
+
+            player.playing = Some(PlayingTrack {
+                track,
+                downbeat: pending
+                    .at
+                    .after(Duration::from_secs_f64(track.offset / track.speed as f64)),
+                last_beat: None,
+            });
+        }
+        MusicTransition::Stop => {
+            retire_current(&mut player, pending.at, &named, &mut commands);
+            player.playing = None;
+        }
+    }
+}
+
+/// Mark the current track to be stopped at `stop_at`.
+fn retire_current(
+    player: &mut MusicPlayer,
+    stop_at: AudioInstant,
+    named: &Query<(Entity, &Name)>,
+    commands: &mut Commands,
+) {
+    if player.playing.is_none() {
+        return;
+    }
+
+    let music = Name::new(MUSIC_NAME);
+    let mut voices = Vec::new();
+
+    for (entity, name) in named {
+        if name == &music {
+            commands.entity(entity).insert(Name::new(OUTGOING_NAME));
+            voices.push(entity);
+        }
+    }
+
+    player.outgoing.push(OutgoingTrack { stop_at, voices });
+}
+
+/// Keep the beat grid in step when the music changes speed.
+fn observe_speed_event(
+    trigger: Trigger<SpeedEvent>,
+    mut player: ResMut<MusicPlayer>,
+    clock: Res<AudioClock>,
+) {
+    if trigger.name != MUSIC_NAME {
+        return;
+    }
+
+    let Some(playing) = &mut player.playing else {
+        return;
+    };
+
+    // The speed change takes effect around now, so we re-anchor the
+    // downbeat such that the current position in the music is preserved.
+    let position = clock.now.0 - playing.downbeat.0;
+    let beats = position / playing.track.beat_duration().as_secs_f64();
+
+    playing.track.speed = trigger.speed;
+    playing.downbeat =
+        AudioInstant(clock.now.0 - beats * playing.track.beat_duration().as_secs_f64());
+}
+
+fn emit_beats(mut player: ResMut<MusicPlayer>, clock: Res<AudioClock>, mut commands: Commands) {
+    let Some(playing) = &mut player.playing else {
+        return;
+    };
+
+    let Some(current) = playing.beat_at(clock.now) else {
+        return;
+    };
+
+    let first = playing.last_beat.map(|b| b + 1).unwrap_or(0);
+    let beats_per_bar = playing.track.beats_per_bar.max(1) as u64;
+
+    for index in first..=current {
+        let bar = index / beats_per_bar;
+        let beat_in_bar = (index % beats_per_bar) as u32;
+
+        commands.trigger(MusicBeat {
+            index,
+            bar,
+            beat_in_bar,
+        });
+
+        if beat_in_bar == 0 {
+            commands.trigger(MusicBar { index: bar });
+        }
+    }
+
+    playing.last_beat = Some(current);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Beats land every half second from one second in, with four to a bar.
+    fn playing() -> PlayingTrack {
+        PlayingTrack {
+            track: MusicTrack {
+                bpm: 120.0,
+                beats_per_bar: 4,
+                ..Default::default()
+            },
+            downbeat: AudioInstant(1.0),
+            last_beat: None,
+        }
+    }
+
+    #[test]
+    fn immediate_is_not_quantized() {
+        let at = AudioInstant(1.2);
+
+        assert_eq!(playing().next_boundary(Quantize::Immediate, at), at);
+    }
+
+    #[test]
+    fn beats_round_up() {
+        let playing = playing();
+
+        assert_eq!(
+            playing.next_boundary(Quantize::Beat, AudioInstant(1.2)),
+            AudioInstant(1.5)
+        );
+        assert_eq!(
+            playing.next_boundary(Quantize::Beat, AudioInstant(1.5)),
+            AudioInstant(1.5)
+        );
+    }
+
+    #[test]
+    fn bars_round_up() {
+        let playing = playing();
+
+        assert_eq!(
+            playing.next_boundary(Quantize::Bar, AudioInstant(1.2)),
+            AudioInstant(3.0)
+        );
+        assert_eq!(
+            playing.next_boundary(Quantize::Bar, AudioInstant(3.0)),
+            AudioInstant(3.0)
+        );
+        assert_eq!(
+            playing.next_boundary(Quantize::Bar, AudioInstant(3.6)),
+            AudioInstant(5.0)
+        );
+    }
+
+    #[test]
+    fn boundaries_before_the_downbeat_wait_for_it() {
+        let playing = playing();
+
+        assert_eq!(
+            playing.next_boundary(Quantize::Beat, AudioInstant(0.2)),
+            AudioInstant(1.0)
+        );
+        assert_eq!(
+            playing.next_boundary(Quantize::Bar, AudioInstant(0.2)),
+            AudioInstant(1.0)
+        );
+    }
+
+    #[test]
+    fn speed_shortens_beats() {
+        let mut playing = playing();
+        playing.track.speed = 2.0;
+
+        assert_eq!(
+            playing.next_boundary(Quantize::Beat, AudioInstant(1.1)),
+            AudioInstant(1.25)
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use bevy::prelude::*;
+
+pub fn occlusion_plugin(app: &mut App) {
+    app.add_systems(PostUpdate, compute_occlusion);
+}
+
+/// The cutoff used when nothing stands between the listener and an emitter.
+pub const OPEN_CUTOFF_HZ: f32 = 20_000.0;
+
+/// A shape in the scene that blocks sound.
+///
+/// Occluders live in the same space as [`AudioEvent::position`][crate::audio::AudioEvent],
+/// where the listener sits at the origin. Any spatial sound whose
+/// direct path to the listener crosses the shape is attenuated
+/// and low-passed.
+#[derive(Debug, Component, Clone)]
+pub struct Occluder {
+    pub shape: OccluderShape,
+    /// The linear gain applied to occluded sounds.
+    pub gain: f32,
+    /// The low-pass cutoff applied to occluded sounds.
+    pub cutoff_hz: f32,
+}
+
+impl Default for Occluder {
+    fn default() -> Self {
+        Self {
+            shape: OccluderShape::Segment(Vec2::ZERO, Vec2::ZERO),
+            gain: 0.5,
+            cutoff_hz: 1_200.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum OccluderShape {
+    /// A thin wall between two points, like a tree line.
+    Segment(Vec2, Vec2),
+    Rect {
+        center: Vec2,
+        half_size: Vec2,
+    },
+    Circle {
+        center: Vec2,
+        radius: f32,
+    },
+}
+
+impl OccluderShape {
+    /// Returns `true` if the segment from `start` to `end` touches the shape.
+    pub fn intersects(&self, start: Vec2, end: Vec2) -> bool {
+        match *self {
+            OccluderShape::Segment(a, b) => segments_intersect(start, end, a, b),
+            OccluderShape::Rect { center, half_size } => {
+                let min = center - half_size;
+                let max = center + half_size;
+                let corners = [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+
+                let inside = |p: Vec2| p.cmpge(min).all() && p.cmple(max).all();
+
+                inside(start)
+                    || inside(end)
+                    || (0..4)
+                        .any(|i| segments_intersect(start, end, corners[i], corners[(i + 1) % 4]))
+            }
+            OccluderShape::Circle { center, radius } => {
+                let ray = end - start;
+                let t = ((center - start).dot(ray) / ray.length_squared().max(f32::EPSILON))
+                    .clamp(0.0, 1.0);
+
+                (start + ray * t).distance_squared(center) <= radius * radius
+            }
+        }
+    }
+}
+
+fn segments_intersect(p1: Vec2, p2: Vec2, q1: Vec2, q2: Vec2) -> bool {
+    let r = p2 - p1;
+    let s = q2 - q1;
+    let denominator = r.perp_dot(s);
+
+    if denominator.abs() <= f32::EPSILON {
+        return false;
+    }
+
+    let t = (q1 - p1).perp_dot(s) / denominator;
+    let u = (q1 - p1).perp_dot(r) / denominator;
+
+    (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)
+}
+
+/// The position of a playing spatial sound, relative to the listener.
+///
+/// Engines insert this on their spatial voices.
+#[derive(Debug, Component, Clone, Copy)]
+#[require(Occlusion)]
+pub struct EmitterPosition(pub Vec2);
+
+/// The filtering each engine should apply to a spatial voice.
+#[derive(Debug, Component, Clone, Copy, PartialEq)]
+pub struct Occlusion {
+    pub gain: f32,
+    pub cutoff_hz: f32,
+}
+
+impl Default for Occlusion {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            cutoff_hz: OPEN_CUTOFF_HZ,
+        }
+    }
+}
+
+fn compute_occlusion(
+    mut emitters: Query<(&EmitterPosition, &mut Occlusion)>,
+    occluders: Query<&Occluder>,
+) {
+    for (position, mut occlusion) in &mut emitters {
+        let new_occlusion = occluders
+            .iter()
+            .filter(|o| o.shape.intersects(Vec2::ZERO, position.0))
+            .fold(Occlusion::default(), |acc, o| Occlusion {
+                gain: acc.gain * o.gain,
+                cutoff_hz: acc.cutoff_hz.min(o.cutoff_hz),
+            });
+
+        occlusion.set_if_neq(new_occlusion);
+    }
+}
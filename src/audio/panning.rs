@@ -0,0 +1,260 @@
+//! Vector-based amplitude panning over a configurable speaker layout.
+//!
+//! For stereo output, each engine's built-in spatializer is used as before.
+//! For surround layouts, spatial voices receive a [`SpeakerGains`] component
+//! that the engines apply per output channel.
+
+use bevy::prelude::*;
+use clap::ValueEnum;
+
+use crate::audio::occlusion::EmitterPosition;
+
+pub fn panning_plugin(app: &mut App) {
+    app.init_resource::<OutputLayout>()
+        .add_systems(PostUpdate, compute_speaker_gains);
+}
+
+/// The output channel layout.
+///
+/// Channels are in the usual WAVE order: front left, front right,
+/// center, LFE, then the rear and side pairs.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SpeakerLayout {
+    #[default]
+    Stereo,
+    #[value(name = "5.1")]
+    Surround51,
+    #[value(name = "7.1")]
+    Surround71,
+}
+
+impl SpeakerLayout {
+    /// The azimuth of each output channel in degrees, where 0 is straight ahead
+    /// and positive angles are to the right.
+    ///
+    /// The LFE channel has no position, and so receives no panned signal.
+    pub fn azimuths(&self) -> &'static [Option<f32>] {
+        match self {
+            SpeakerLayout::Stereo => &[Some(-30.0), Some(30.0)],
+            SpeakerLayout::Surround51 => &[
+                Some(-30.0),
+                Some(30.0),
+                Some(0.0),
+                None,
+                Some(-110.0),
+                Some(110.0),
+            ],
+            SpeakerLayout::Surround71 => &[
+                Some(-30.0),
+                Some(30.0),
+                Some(0.0),
+                None,
+                Some(-150.0),
+                Some(150.0),
+                Some(-90.0),
+                Some(90.0),
+            ],
+        }
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.azimuths().len()
+    }
+
+    pub fn is_surround(&self) -> bool {
+        *self != SpeakerLayout::Stereo
+    }
+
+    /// Calculate power-normalized VBAP gains for a source in the given direction.
+    ///
+    /// Directions use the same space as [`AudioEvent::position`][crate::audio::AudioEvent],
+    /// with `+y` ahead of the listener and `+x` to the right.
+    pub fn vbap(&self, direction: Vec2) -> Vec<f32> {
+        let azimuths = self.azimuths();
+        let mut gains = vec![0.0; azimuths.len()];
+
+        let mut speakers = azimuths
+            .iter()
+            .enumerate()
+            .filter_map(|(i, a)| a.map(|a| (i, a)))
+            .collect::<Vec<_>>();
+
+        // A source on top of the listener is spread evenly.
+        let Some(direction) = direction.try_normalize() else {
+            let gain = (speakers.len() as f32).sqrt().recip();
+            for (i, _) in speakers {
+                gains[i] = gain;
+            }
+            return gains;
+        };
+
+        speakers.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let unit = |azimuth: f32| {
+            let radians = azimuth.to_radians();
+            Vec2::new(radians.sin(), radians.cos())
+        };
+
+        for pair in 0..speakers.len() {
+            let (a, a_azimuth) = speakers[pair];
+            let (b, b_azimuth) = speakers[(pair + 1) % speakers.len()];
+
+            // Pairs spanning 180 degrees or more can't be inverted,
+            // which happens with stereo's rear gap.
+            let span = (b_azimuth - a_azimuth).rem_euclid(360.0);
+            if span == 0.0 || span >= 180.0 {
+                continue;
+            }
+
+            let basis = Mat2::from_cols(unit(a_azimuth), unit(b_azimuth));
+            let pair_gains = basis.inverse() * direction;
+
+            if pair_gains.min_element() >= -1e-4 {
+                let pair_gains = pair_gains.max(Vec2::ZERO).normalize_or_zero();
+                gains[a] = pair_gains.x;
+                gains[b] = pair_gains.y;
+
+                return gains;
+            }
+        }
+
+        // Directions outside all pairs snap to the closest speaker.
+        if let Some((closest, _)) = speakers.iter().max_by(|a, b| {
+            unit(a.1)
+                .dot(direction)
+                .total_cmp(&unit(b.1).dot(direction))
+        }) {
+            gains[*closest] = 1.0;
+        }
+
+        gains
+    }
+}
+
+/// Distance attenuation for VBAP voices, tuned to roughly
+/// match Firewheel's basic spatializer.
+pub fn distance_gain(distance: f32) -> f32 {
+    10f32.powf(-0.015 * distance)
+}
+
+/// The gain of each output channel for a source at the given position,
+/// including distance attenuation.
+pub fn speaker_gains(layout: SpeakerLayout, position: Vec2) -> Vec<f32> {
+    let attenuation = distance_gain(position.length());
+
+    layout
+        .vbap(position)
+        .into_iter()
+        .map(|gain| gain * attenuation)
+        .collect()
+}
+
+/// The configured output layout.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct OutputLayout(pub SpeakerLayout);
+
+/// The per-channel gain of a spatial voice, including distance attenuation.
+///
+/// This is only computed for surround layouts.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct SpeakerGains(pub Vec<f32>);
+
+fn compute_speaker_gains(
+    emitters: Query<(Entity, &EmitterPosition, Option<&SpeakerGains>)>,
+    layout: Res<OutputLayout>,
+    mut commands: Commands,
+) {
+    if !layout.0.is_surround() {
+        return;
+    }
+
+    for (entity, position, current) in &emitters {
+        let gains = speaker_gains(layout.0, position.0);
+
+        if current.is_none_or(|c| c.0 != gains) {
+            commands.entity(entity).insert(SpeakerGains(gains));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUTS: [SpeakerLayout; 3] = [
+        SpeakerLayout::Stereo,
+        SpeakerLayout::Surround51,
+        SpeakerLayout::Surround71,
+    ];
+
+    fn energy(gains: &[f32]) -> f32 {
+        gains.iter().map(|g| g * g).sum()
+    }
+
+    fn direction(azimuth: f32) -> Vec2 {
+        let radians = azimuth.to_radians();
+        Vec2::new(radians.sin(), radians.cos())
+    }
+
+    #[test]
+    fn energy_is_preserved() {
+        for layout in LAYOUTS {
+            for azimuth in (0..360).step_by(5) {
+                let gains = layout.vbap(direction(azimuth as f32));
+                assert!(
+                    (energy(&gains) - 1.0).abs() < 1e-4,
+                    "{layout:?} at {azimuth} degrees has energy {}",
+                    energy(&gains)
+                );
+            }
+
+            assert!((energy(&layout.vbap(Vec2::ZERO)) - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn speaker_directions_use_one_speaker() {
+        for layout in LAYOUTS {
+            for (channel, azimuth) in layout.azimuths().iter().enumerate() {
+                let Some(azimuth) = azimuth else {
+                    continue;
+                };
+
+                let gains = layout.vbap(direction(*azimuth));
+                for (other, gain) in gains.iter().enumerate() {
+                    let expected = if other == channel { 1.0 } else { 0.0 };
+                    assert!(
+                        (gain - expected).abs() < 1e-4,
+                        "{layout:?} channel {other} has gain {gain} at {azimuth} degrees"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn center_of_a_pair_is_split_evenly() {
+        let gains = SpeakerLayout::Stereo.vbap(Vec2::Y);
+        let expected = 0.5f32.sqrt();
+
+        assert!((gains[0] - expected).abs() < 1e-4);
+        assert!((gains[1] - expected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn lfe_is_never_panned() {
+        for layout in [SpeakerLayout::Surround51, SpeakerLayout::Surround71] {
+            for azimuth in (0..360).step_by(15) {
+                assert_eq!(layout.vbap(direction(azimuth as f32))[3], 0.0);
+            }
+            assert_eq!(layout.vbap(Vec2::ZERO)[3], 0.0);
+        }
+    }
+
+    #[test]
+    fn stereo_rear_snaps_to_the_closest_speaker() {
+        let gains = SpeakerLayout::Stereo.vbap(direction(120.0));
+
+        assert_eq!(gains, vec![0.0, 1.0]);
+    }
+}
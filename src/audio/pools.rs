@@ -0,0 +1,302 @@
+//! Random containers of samples.
+//!
+//! A [`SamplePool`] is registered under a name, and any [`AudioEvent`]
+//! whose `sample` is that name plays one of the pool's samples instead.
+//! Pools decide which sample comes next and randomize the pitch and volume.
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use rand::{Rng, seq::SliceRandom};
+use std::{collections::VecDeque, ops::RangeInclusive};
+
+use crate::audio::AudioEvent;
+
+pub fn pools_plugin(app: &mut App) {
+    app.init_resource::<SamplePools>();
+}
+
+/// How a [`SamplePool`] picks its next sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolMode {
+    /// Any sample, every time.
+    Random,
+    /// Any sample other than the last `n` played.
+    NoRepeat(usize),
+    /// Every sample once, in a random order, before any repeats.
+    Shuffle,
+}
+
+/// A set of interchangeable samples.
+#[derive(Debug, Clone)]
+pub struct SamplePool {
+    pub samples: &'static [&'static str],
+    /// The relative likelihood of each sample. Empty weights are uniform,
+    /// and so are weights that are all zero.
+    ///
+    /// Weights are ignored in [`PoolMode::Shuffle`].
+    pub weights: &'static [f32],
+    pub mode: PoolMode,
+    /// Multiplies the event's speed.
+    pub speed: RangeInclusive<f32>,
+    /// Multiplies the event's volume.
+    pub volume: RangeInclusive<f32>,
+}
+
+impl Default for SamplePool {
+    fn default() -> Self {
+        Self {
+            samples: &[],
+            weights: &[],
+            mode: PoolMode::Random,
+            speed: 1.0..=1.0,
+            volume: 1.0..=1.0,
+        }
+    }
+}
+
+struct PoolState {
+    pool: SamplePool,
+    /// The most recently played samples, newest last.
+    history: VecDeque<usize>,
+    /// The samples left before a shuffled pool starts over.
+    bag: Vec<usize>,
+}
+
+impl PoolState {
+    fn weight(&self, index: usize) -> f32 {
+        self.pool.weights.get(index).copied().unwrap_or(1.0)
+    }
+
+    fn next_index(&mut self, rng: &mut impl Rng) -> Option<usize> {
+        let len = self.pool.samples.len();
+
+        let index = match self.pool.mode {
+            PoolMode::Random => self.choose_weighted(0..len, rng)?,
+            PoolMode::NoRepeat(n) => {
+                // We can't avoid more samples than the pool has.
+                let avoid = n.min(len.saturating_sub(1));
+                let recent = self
+                    .history
+                    .iter()
+                    .rev()
+                    .take(avoid)
+                    .copied()
+                    .collect::<Vec<_>>();
+
+                self.choose_weighted((0..len).filter(|i| !recent.contains(i)), rng)?
+            }
+            PoolMode::Shuffle => {
+                if self.bag.is_empty() {
+                    self.bag.extend(0..len);
+                    self.bag.shuffle(rng);
+
+                    // Avoid playing the same sample twice across the seam.
+                    if len > 1 && self.bag.last() == self.history.back() {
+                        self.bag.swap(0, len - 1);
+                    }
+                }
+
+                self.bag.pop()?
+            }
+        };
+
+        self.history.push_back(index);
+        if self.history.len() > len {
+            self.history.pop_front();
+        }
+
+        Some(index)
+    }
+
+    /// Pick one of `candidates`, falling back to a uniform choice
+    /// if none of them has a positive weight.
+    fn choose_weighted(
+        &self,
+        candidates: impl Iterator<Item = usize>,
+        rng: &mut impl Rng,
+    ) -> Option<usize> {
+        let candidates = candidates.collect::<Vec<_>>();
+
+        candidates
+            .choose_weighted(rng, |i| self.weight(*i))
+            .ok()
+            .or_else(|| candidates.choose(rng))
+            .copied()
+    }
+}
+
+/// Every registered [`SamplePool`], by name.
+#[derive(Resource, Default)]
+pub struct SamplePools(HashMap<&'static str, PoolState>);
+
+impl SamplePools {
+    pub fn insert(&mut self, name: &'static str, pool: SamplePool) {
+        if !pool.weights.is_empty() && pool.weights.iter().all(|w| *w <= 0.0) {
+            warn!("sample pool \"{name}\" has no positive weights, so it will pick uniformly");
+        }
+
+        self.0.insert(
+            name,
+            PoolState {
+                pool,
+                history: VecDeque::new(),
+                bag: Vec::new(),
+            },
+        );
+    }
+
+    /// Resolve an event that may refer to a pool into one that refers to a sample.
+    ///
+    /// Events that don't name a pool are returned as they are.
+    pub fn resolve(&mut self, event: &AudioEvent) -> AudioEvent {
+        let mut event = event.clone();
+
+        let Some(state) = self.0.get_mut(event.sample) else {
+            return event;
+        };
+
+        let mut rng = rand::thread_rng();
+        let Some(index) = state.next_index(&mut rng) else {
+            return event;
+        };
+
+        event.sample = state.pool.samples[index];
+        event.speed *= rng.gen_range(state.pool.speed.clone());
+        event.volume *= rng.gen_range(state.pool.volume.clone());
+
+        event
+    }
+}
+
+pub trait RegisterSamplePool {
+    /// Make a [`SamplePool`] available to [`AudioEvent`]s under `name`.
+    fn register_sample_pool(&mut self, name: &'static str, pool: SamplePool) -> &mut Self;
+}
+
+impl RegisterSamplePool for App {
+    fn register_sample_pool(&mut self, name: &'static str, pool: SamplePool) -> &mut Self {
+        self.init_resource::<SamplePools>();
+        self.world_mut()
+            .resource_mut::<SamplePools>()
+            .insert(name, pool);
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    const SAMPLES: &[&str] = &["a.ogg", "b.ogg", "c.ogg", "d.ogg"];
+
+    fn state(pool: SamplePool) -> PoolState {
+        PoolState {
+            pool,
+            history: VecDeque::new(),
+            bag: Vec::new(),
+        }
+    }
+
+    fn picks(pool: SamplePool, count: usize) -> Vec<usize> {
+        let mut state = state(pool);
+        let mut rng = StdRng::seed_from_u64(7);
+
+        (0..count)
+            .map(|_| state.next_index(&mut rng).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn no_repeat_avoids_recent_samples() {
+        let picks = picks(
+            SamplePool {
+                samples: SAMPLES,
+                mode: PoolMode::NoRepeat(2),
+                ..Default::default()
+            },
+            200,
+        );
+
+        for window in picks.windows(3) {
+            assert_ne!(window[2], window[1]);
+            assert_ne!(window[2], window[0]);
+        }
+    }
+
+    #[test]
+    fn no_repeat_is_limited_by_the_pool() {
+        let picks = picks(
+            SamplePool {
+                samples: &SAMPLES[..2],
+                mode: PoolMode::NoRepeat(5),
+                ..Default::default()
+            },
+            20,
+        );
+
+        for pair in picks.windows(2) {
+            assert_ne!(pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn shuffle_plays_everything_before_repeating() {
+        let picks = picks(
+            SamplePool {
+                samples: SAMPLES,
+                mode: PoolMode::Shuffle,
+                ..Default::default()
+            },
+            SAMPLES.len() * 50,
+        );
+
+        for round in picks.chunks(SAMPLES.len()) {
+            let mut round = round.to_vec();
+            round.sort();
+            assert_eq!(round, vec![0, 1, 2, 3]);
+        }
+
+        for pair in picks.windows(2) {
+            assert_ne!(pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn zero_weights_are_never_picked() {
+        let picks = picks(
+            SamplePool {
+                samples: SAMPLES,
+                weights: &[0.0, 1.0, 0.0, 1.0],
+                ..Default::default()
+            },
+            200,
+        );
+
+        assert!(picks.iter().all(|i| *i == 1 || *i == 3));
+    }
+
+    #[test]
+    fn all_zero_weights_pick_uniformly() {
+        let picks = picks(
+            SamplePool {
+                samples: SAMPLES,
+                weights: &[0.0; 4],
+                ..Default::default()
+            },
+            200,
+        );
+
+        for index in 0..SAMPLES.len() {
+            assert!(picks.contains(&index));
+        }
+    }
+
+    #[test]
+    fn empty_pools_pick_nothing() {
+        let mut state = state(SamplePool::default());
+        let mut rng = StdRng::seed_from_u64(7);
+
+        assert_eq!(state.next_index(&mut rng), None);
+    }
+}
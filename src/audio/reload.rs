@@ -0,0 +1,211 @@
+//! Hot reloading for samples.
+//!
+//! With the `hot_reload` feature, Bevy watches `assets` for changes.
+//! Engines prepare voices from the latest [`AudioSample`], so new sounds
+//! pick up an edit right away while sounds that are already playing
+//! finish with the old data. Looping voices with a name can also be
+//! restarted, crossfading from the old data to the new.
+//!
+//! Streamed samples aren't assets, so their files are checked for
+//! changes directly while a named, looping stream is playing.
+
+use bevy::prelude::*;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::audio::{
+    AudioEvent, VoiceVolume, VolumeFade, VolumeFadeEvent,
+    sample::AudioSample,
+    stream::{Streaming, asset_path},
+};
+
+pub fn reload_plugin(app: &mut App) {
+    app.init_resource::<HotReload>()
+        .add_systems(Update, restart_looping_voices);
+
+    if cfg!(feature = "hot_reload") {
+        app.add_systems(Update, restart_looping_streams);
+    }
+}
+
+/// How reloaded samples affect sounds that are already playing.
+#[derive(Resource, Debug, Clone)]
+pub struct HotReload {
+    /// Restart named, looping voices when their sample changes.
+    pub restart_looping: bool,
+    /// The length of the crossfade when restarting, in seconds.
+    pub crossfade: f32,
+}
+
+impl Default for HotReload {
+    fn default() -> Self {
+        Self {
+            restart_looping: true,
+            crossfade: 0.25,
+        }
+    }
+}
+
+/// A looping voice, along with what it would take to start it again.
+///
+/// Engines insert this on every looping voice that plays a sample.
+#[derive(Component, Debug, Clone)]
+pub struct LoopingVoice {
+    pub sample: Handle<AudioSample>,
+    pub event: AudioEvent,
+}
+
+impl LoopingVoice {
+    pub fn new(sample: Handle<AudioSample>, event: AudioEvent) -> Self {
+        Self { sample, event }
+    }
+}
+
+/// A looping voice streamed from disk, along with what it would take to start it again.
+///
+/// Engines insert this on every looping stream.
+#[derive(Component, Debug, Clone)]
+pub struct LoopingStream {
+    pub event: AudioEvent,
+    path: PathBuf,
+    /// When the file was last modified, as of the stream starting.
+    modified: Option<SystemTime>,
+}
+
+impl LoopingStream {
+    pub fn new(event: AudioEvent) -> Self {
+        let path = asset_path(event.sample);
+        let modified = last_modified(&path);
+
+        Self {
+            event,
+            path,
+            modified,
+        }
+    }
+}
+
+fn last_modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn restart_looping_voices(
+    mut events: EventReader<AssetEvent<AudioSample>>,
+    settings: Res<HotReload>,
+    voices: Query<(Entity, &LoopingVoice, &VoiceVolume)>,
+    mut commands: Commands,
+) {
+    for event in events.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+
+        if !settings.restart_looping {
+            continue;
+        }
+
+        for (entity, voice, volume) in &voices {
+            let Some(name) = voice.event.name else {
+                continue;
+            };
+
+            if voice.sample.id() != *id {
+                continue;
+            }
+
+            commands.entity(entity).remove::<LoopingVoice>();
+            restart(
+                entity,
+                name,
+                AudioEvent {
+                    handle: Some(voice.sample.clone()),
+                    ..voice.event.clone()
+                },
+                volume.0,
+                &settings,
+                &mut commands,
+            );
+        }
+    }
+}
+
+/// How often streamed files are checked for changes.
+const STREAM_POLL_SECONDS: f32 = 0.5;
+
+fn restart_looping_streams(
+    time: Res<Time>,
+    mut since_poll: Local<f32>,
+    settings: Res<HotReload>,
+    streaming: Res<Streaming>,
+    voices: Query<(Entity, &LoopingStream, &VoiceVolume)>,
+    mut commands: Commands,
+) {
+    *since_poll += time.delta_secs();
+    if *since_poll < STREAM_POLL_SECONDS || !settings.restart_looping {
+        return;
+    }
+    *since_poll = 0.0;
+
+    for (entity, stream, volume) in &voices {
+        let Some(name) = stream.event.name else {
+            continue;
+        };
+
+        if last_modified(&stream.path) == stream.modified {
+            continue;
+        }
+
+        // The edit may have moved the file across the streaming threshold.
+        streaming.forget(stream.event.sample);
+
+        commands.entity(entity).remove::<LoopingStream>();
+        restart(
+            entity,
+            name,
+            stream.event.clone(),
+            volume.0,
+            &settings,
+            &mut commands,
+        );
+    }
+}
+
+/// Crossfade from a playing voice to a fresh one.
+fn restart(
+    entity: Entity,
+    name: &'static str,
+    event: AudioEvent,
+    volume: f32,
+    settings: &HotReload,
+    commands: &mut Commands,
+) {
+    // The old voice steps aside, so fades by name reach the new one.
+    commands.entity(entity).insert((
+        Name::new(format!("{name}-outgoing")),
+        VolumeFade {
+            timer: Timer::new(Duration::from_secs_f32(settings.crossfade), TimerMode::Once),
+            event: VolumeFadeEvent {
+                name,
+                start: volume,
+                end: 0.0,
+                seconds: settings.crossfade,
+                stop: true,
+            },
+        },
+    ));
+
+    commands.trigger(AudioEvent {
+        volume: 0.0,
+        start: None,
+        ..event
+    });
+    commands.trigger(VolumeFadeEvent {
+        name,
+        start: 0.0,
+        end: volume,
+        seconds: settings.crossfade,
+        stop: false,
+    });
+}
@@ -1,18 +1,38 @@
 use bevy::prelude::*;
 use std::time::Duration;
 
-use crate::audio::AudioEvent;
+use crate::audio::{
+    AudioEvent, VoiceVolume, VolumeFade, VolumeFadeEvent,
+    clock::{AudioClock, AudioInstant, ScheduledStart},
+    rhythm::{MIN_STEP_DURATION, RhythmPattern, RhythmStep},
+};
 
 pub fn repeater_plugin(app: &mut App) {
-    app.add_systems(Update, handle_repeaters);
+    app.add_systems(Update, handle_repeaters)
+        .add_observer(observe_stop_event);
 }
 
 /// A simple utility for repeatedly playing sounds on an arbitrary schedule.
+///
+/// Sounds are scheduled slightly ahead on the [`AudioClock`],
+/// so the timing doesn't depend on the frame rate. The time
+/// between sounds comes from either a closure or a [`RhythmPattern`].
+///
+/// By default, a repeater runs until its entity is despawned.
+/// It can also be limited to a number of repetitions or a total
+/// duration, or stopped by name with a [`StopRepeaterEvent`].
+/// In those cases, a [`RepeaterFinished`] is triggered when it ends.
 #[derive(Component)]
 pub struct SoundRepeater {
-    timer: Timer,
+    first_delay: Duration,
+    next_at: Option<AudioInstant>,
     next_sound: Box<dyn FnMut() -> AudioEvent + Send + Sync + 'static>,
-    next_duration: Box<dyn FnMut() -> Duration + Send + Sync + 'static>,
+    timing: Timing,
+    name: Option<&'static str>,
+    max_repetitions: Option<u32>,
+    max_duration: Option<Duration>,
+    repetitions: u32,
+    started_at: Option<AudioInstant>,
 }
 
 impl SoundRepeater {
@@ -22,20 +42,218 @@ impl SoundRepeater {
     ) -> Self {
         Self {
             next_sound: Box::new(sound),
-            timer: Timer::new(duration(), TimerMode::Repeating),
-            next_duration: Box::new(duration),
+            first_delay: duration(),
+            next_at: None,
+            timing: Timing::Closure(Box::new(duration)),
+            name: None,
+            max_repetitions: None,
+            max_duration: None,
+            repetitions: 0,
+            started_at: None,
         }
     }
+
+    /// Play sounds following a rhythm, starting with its first step.
+    pub fn with_rhythm(
+        sound: impl FnMut() -> AudioEvent + Send + Sync + 'static,
+        pattern: RhythmPattern,
+    ) -> Self {
+        Self {
+            next_sound: Box::new(sound),
+            first_delay: Duration::ZERO,
+            next_at: None,
+            timing: Timing::Rhythm { pattern, index: 0 },
+            name: None,
+            max_repetitions: None,
+            max_duration: None,
+            repetitions: 0,
+            started_at: None,
+        }
+    }
+
+    /// Name the repeater, so it can be stopped with a [`StopRepeaterEvent`].
+    ///
+    /// Sounds without a name of their own are given the same one.
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Stop after playing `count` sounds.
+    pub fn with_repetitions(mut self, count: u32) -> Self {
+        self.max_repetitions = Some(count);
+        self
+    }
+
+    /// Stop once `duration` has passed since the repeater started.
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.max_duration = Some(duration);
+        self
+    }
+}
+
+enum Timing {
+    Closure(Box<dyn FnMut() -> Duration + Send + Sync + 'static>),
+    Rhythm {
+        pattern: RhythmPattern,
+        index: usize,
+    },
+}
+
+impl Timing {
+    /// Advance to the next step, returning it along with the time until the one after.
+    fn next_step(&mut self) -> (RhythmStep, Duration) {
+        match self {
+            Timing::Closure(duration) => (RhythmStep::default(), duration().max(MIN_STEP_DURATION)),
+            Timing::Rhythm { pattern, index } => {
+                let step = pattern
+                    .steps
+                    .get(*index % pattern.steps.len().max(1))
+                    .copied()
+                    .unwrap_or_default();
+                let duration = pattern.step_duration(*index);
+                *index += 1;
+
+                (step, duration)
+            }
+        }
+    }
+}
+
+/// Stop every [`SoundRepeater`] with a name.
+///
+/// Sounds the repeaters scheduled that haven't started yet are cancelled.
+#[derive(Event, Debug, Clone)]
+pub struct StopRepeaterEvent {
+    pub name: &'static str,
+    /// Fade out sounds that are still playing over this many seconds.
+    ///
+    /// Otherwise, they play out normally.
+    pub fade_out: Option<f32>,
+}
+
+/// Triggered when a [`SoundRepeater`] ends, after which its entity is despawned.
+#[derive(Event, Debug, Clone)]
+pub struct RepeaterFinished {
+    pub repeater: Entity,
+    pub name: Option<&'static str>,
+}
+
+fn finish(entity: Entity, repeater: &SoundRepeater, commands: &mut Commands) {
+    commands.trigger(RepeaterFinished {
+        repeater: entity,
+        name: repeater.name,
+    });
+    commands.entity(entity).despawn();
 }
 
-fn handle_repeaters(mut q: Query<&mut SoundRepeater>, mut commands: Commands, time: Res<Time>) {
-    let delta = time.delta();
+/// The most steps a repeater may take in one frame.
+///
+/// This only comes into play after a long stall, when
+/// the rest of the backlog is picked up next frame.
+const MAX_STEPS_PER_FRAME: usize = 64;
+
+fn handle_repeaters(
+    mut q: Query<(Entity, &mut SoundRepeater)>,
+    mut commands: Commands,
+    clock: Res<AudioClock>,
+) {
+    for (entity, mut repeater) in &mut q {
+        let first_delay = repeater.first_delay;
+        let started_at = *repeater.started_at.get_or_insert(clock.now);
+        let mut next_at = *repeater
+            .next_at
+            .get_or_insert_with(|| clock.now.after(first_delay));
+
+        let ends_at = repeater.max_duration.map(|d| started_at.after(d));
+
+        for _ in 0..MAX_STEPS_PER_FRAME {
+            let out_of_repetitions = repeater
+                .max_repetitions
+                .is_some_and(|max| repeater.repetitions >= max);
+            let out_of_time = ends_at.is_some_and(|end| next_at > end);
+
+            if out_of_repetitions || out_of_time {
+                finish(entity, &repeater, &mut commands);
+                break;
+            }
+
+            if next_at > clock.horizon() {
+                break;
+            }
+
+            let (step, next_duration) = repeater.timing.next_step();
 
-    for mut repeater in &mut q {
-        if repeater.timer.tick(delta).just_finished() {
-            commands.trigger((repeater.next_sound)());
-            let next_duration = (repeater.next_duration)();
-            repeater.timer.set_duration(next_duration);
+            if !step.rest {
+                let mut event = (repeater.next_sound)();
+                event.start = Some(next_at);
+                event.volume *= step.volume;
+                event.speed *= step.speed;
+                if let Some(position) = &mut event.position {
+                    *position += step.offset;
+                }
+                if event.name.is_none() {
+                    event.name = repeater.name;
+                }
+
+                repeater.repetitions += 1;
+                commands.trigger(event);
+            }
+
+            next_at = next_at.after(next_duration);
         }
+
+        repeater.next_at = Some(next_at);
     }
 }
+
+fn observe_stop_event(
+    trigger: Trigger<StopRepeaterEvent>,
+    repeaters: Query<(Entity, &SoundRepeater)>,
+    voices: Query<(Entity, &Name, Option<&VoiceVolume>, Option<&ScheduledStart>)>,
+    clock: Res<AudioClock>,
+    mut commands: Commands,
+) -> Result {
+    let mut found = false;
+    for (entity, repeater) in &repeaters {
+        if repeater.name == Some(trigger.name) {
+            finish(entity, repeater, &mut commands);
+            found = true;
+        }
+    }
+
+    if !found {
+        return Err(format!("failed to find repeater with name \"{}\"", trigger.name).into());
+    }
+
+    let event_name = Name::new(trigger.name);
+
+    for (voice, name, volume, start) in &voices {
+        if name != &event_name {
+            continue;
+        }
+
+        // Sounds scheduled inside the lookahead are cancelled before they're heard.
+        if start.is_some_and(|start| start.0 > clock.now) {
+            commands.entity(voice).despawn();
+            continue;
+        }
+
+        // More than one sound may still be ringing, so we
+        // fade them directly rather than by name.
+        if let Some(seconds) = trigger.fade_out {
+            commands.entity(voice).insert(VolumeFade {
+                timer: Timer::new(Duration::from_secs_f32(seconds), TimerMode::Once),
+                event: VolumeFadeEvent {
+                    name: trigger.name,
+                    start: volume.map_or(1.0, |volume| volume.0),
+                    end: 0.0,
+                    seconds,
+                    stop: true,
+                },
+            });
+        }
+    }
+
+    Ok(())
+}
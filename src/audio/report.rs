@@ -0,0 +1,216 @@
+//! Reporting on samples that can't be loaded.
+//!
+//! Every file in `assets` is sorted into audio and everything else at
+//! startup, and each sample that fails to load is recorded in the
+//! [`SampleReport`] with its path and the reason, rather than only
+//! surfacing later as silence.
+//!
+//! In strict mode, every referenced sample is checked at startup, loading
+//! decoded samples and opening streamed ones, and the app exits as soon as
+//! any sample fails.
+
+use bevy::{
+    asset::{AssetLoadError, AssetLoadFailedEvent, io::AssetReaderError},
+    platform::collections::HashSet,
+    prelude::*,
+};
+use walkdir::WalkDir;
+
+use crate::audio::{
+    loading::SampleLoader,
+    sample::{AUDIO_EXTENSIONS, AudioSample},
+    stream::{Streaming, probe},
+};
+
+pub fn report_plugin(app: &mut App) {
+    app.init_resource::<SampleReport>()
+        .init_resource::<StrictSamples>()
+        .add_systems(PreStartup, scan_assets)
+        .add_systems(Startup, load_referenced)
+        .add_systems(PreUpdate, (record_loads, enforce_strict).chain());
+}
+
+/// Exit as soon as a sample fails to load.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct StrictSamples(pub bool);
+
+/// Why a sample couldn't be loaded.
+#[derive(Debug, Clone)]
+pub enum SampleFailure {
+    /// There's no file at the sample's path.
+    Missing,
+    /// The file isn't in a format the engines can decode.
+    NotAudio,
+    /// The file looks like audio, but couldn't be read or decoded.
+    Decode(String),
+}
+
+impl core::fmt::Display for SampleFailure {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SampleFailure::Missing => write!(f, "no such file"),
+            SampleFailure::NotAudio => write!(f, "not an audio file"),
+            SampleFailure::Decode(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<&AssetLoadError> for SampleFailure {
+    fn from(e: &AssetLoadError) -> Self {
+        match e {
+            AssetLoadError::AssetReaderError(AssetReaderError::NotFound(_)) => {
+                SampleFailure::Missing
+            }
+            AssetLoadError::MissingAssetLoaderForExtension(_) => SampleFailure::NotAudio,
+            e => SampleFailure::Decode(e.to_string()),
+        }
+    }
+}
+
+/// A sample that failed to load.
+#[derive(Debug, Clone)]
+pub struct SampleLoadError {
+    /// The sample's path within `assets`.
+    pub path: String,
+    pub reason: SampleFailure,
+}
+
+impl SampleLoadError {
+    pub fn new(path: impl Into<String>, reason: SampleFailure) -> Self {
+        Self {
+            path: path.into(),
+            reason,
+        }
+    }
+}
+
+impl core::fmt::Display for SampleLoadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "failed to load sample \"{}\": {}",
+            self.path, self.reason
+        )
+    }
+}
+
+impl core::error::Error for SampleLoadError {}
+
+/// What's in `assets`, and which samples have loaded or failed.
+#[derive(Resource, Debug, Default)]
+pub struct SampleReport {
+    /// Audio files found in `assets`.
+    pub audio: Vec<String>,
+    /// Other files in `assets`, like MIDI files and loop sidecars.
+    pub not_audio: Vec<String>,
+    pub loaded: HashSet<String>,
+    pub failed: Vec<SampleLoadError>,
+}
+
+impl SampleReport {
+    pub fn record_failure(&mut self, error: SampleLoadError) {
+        error!("{error}");
+        self.failed.push(error);
+    }
+}
+
+fn scan_assets(mut report: ResMut<SampleReport>) {
+    let assets_path = std::path::Path::new("assets");
+
+    for entry in WalkDir::new(assets_path).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path();
+        let name: String = path
+            .strip_prefix(assets_path)
+            .unwrap()
+            .to_string_lossy()
+            .into();
+
+        let is_audio = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()));
+
+        if is_audio {
+            report.audio.push(name);
+        } else {
+            report.not_audio.push(name);
+        }
+    }
+
+    info!(
+        "found {} audio files in assets, skipping {} other files",
+        report.audio.len(),
+        report.not_audio.len()
+    );
+}
+
+/// Strict mode checks every referenced sample up front, so failures show up at startup.
+///
+/// Streamed samples aren't loaded as assets, so they're opened instead.
+fn load_referenced(
+    strict: Res<StrictSamples>,
+    mut loader: ResMut<SampleLoader>,
+    streaming: Res<Streaming>,
+    server: Res<AssetServer>,
+    mut report: ResMut<SampleReport>,
+) {
+    if !strict.0 {
+        return;
+    }
+
+    let samples: Vec<_> = loader.referenced().collect();
+    for sample in samples {
+        if !streaming.should_stream(sample) {
+            loader.load(sample, &server);
+        } else if let Err(e) = probe(sample) {
+            report.record_failure(e);
+        }
+    }
+}
+
+fn record_loads(
+    mut loaded: EventReader<AssetEvent<AudioSample>>,
+    mut failed: EventReader<AssetLoadFailedEvent<AudioSample>>,
+    server: Res<AssetServer>,
+    streaming: Res<Streaming>,
+    mut report: ResMut<SampleReport>,
+) {
+    for event in loaded.read() {
+        let AssetEvent::LoadedWithDependencies { id } = event else {
+            continue;
+        };
+
+        if let Some(path) = server.get_path(*id) {
+            report.loaded.insert(path.path().to_string_lossy().into());
+        }
+    }
+
+    for event in failed.read() {
+        report.record_failure(SampleLoadError::new(
+            event.path.path().to_string_lossy(),
+            SampleFailure::from(&event.error),
+        ));
+    }
+
+    for error in streaming.take_failures() {
+        report.record_failure(error);
+    }
+}
+
+fn enforce_strict(
+    strict: Res<StrictSamples>,
+    report: Res<SampleReport>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if strict.0 && !report.failed.is_empty() {
+        error!(
+            "exiting in strict mode, since {} samples failed to load",
+            report.failed.len()
+        );
+        exit.write(AppExit::error());
+    }
+}
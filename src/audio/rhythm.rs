@@ -0,0 +1,216 @@
+//! Declarative rhythms for [`SoundRepeater`][crate::audio::repeater::SoundRepeater].
+//!
+//! A [`RhythmPattern`] is a cycle of steps, each with its own
+//! length, volume, and pitch. Swing and humanization make
+//! the result feel less mechanical.
+
+use bevy::prelude::*;
+use rand::Rng;
+use std::time::Duration;
+
+/// The shortest time between steps.
+///
+/// Steps can't take no time at all, or a repeater would
+/// schedule them endlessly within a single frame.
+pub const MIN_STEP_DURATION: Duration = Duration::from_millis(10);
+
+/// A single step in a [`RhythmPattern`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RhythmStep {
+    /// The time until the next step, in pattern units.
+    pub length: f32,
+    /// Multiplies the sound's volume.
+    pub volume: f32,
+    /// Multiplies the sound's speed.
+    pub speed: f32,
+    /// Moves spatial sounds, such as from one foot to the other.
+    pub offset: Vec2,
+    /// Skip the sound, keeping only the step's length.
+    pub rest: bool,
+}
+
+impl Default for RhythmStep {
+    fn default() -> Self {
+        Self {
+            length: 1.0,
+            volume: 1.0,
+            speed: 1.0,
+            offset: Vec2::ZERO,
+            rest: false,
+        }
+    }
+}
+
+impl RhythmStep {
+    /// A silent step.
+    pub fn rest(length: f32) -> Self {
+        Self {
+            length,
+            rest: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// A repeating cycle of [`RhythmStep`]s.
+#[derive(Debug, Clone)]
+pub struct RhythmPattern {
+    pub steps: Vec<RhythmStep>,
+    /// The duration of one unit of step length.
+    pub unit: Duration,
+    /// Lengthens even-indexed steps by this fraction, from 0 to 1, and
+    /// shortens odd-indexed steps by the same fraction, which delays
+    /// every second step.
+    pub swing: f32,
+    /// Randomly stretches or shrinks each step by up to this fraction.
+    pub humanize: f32,
+}
+
+impl RhythmPattern {
+    pub fn new(unit: Duration, steps: impl IntoIterator<Item = RhythmStep>) -> Self {
+        Self {
+            steps: steps.into_iter().collect(),
+            unit,
+            swing: 0.0,
+            humanize: 0.0,
+        }
+    }
+
+    /// Evenly spaced steps with the given volumes.
+    pub fn accents(unit: Duration, accents: &[f32]) -> Self {
+        Self::new(
+            unit,
+            accents.iter().map(|volume| RhythmStep {
+                volume: *volume,
+                ..Default::default()
+            }),
+        )
+    }
+
+    /// An alternating left and right step.
+    ///
+    /// The leading foot lands a little harder and takes a little longer,
+    /// by `unevenness`, while `stride` sets the distance between the feet.
+    pub fn gait(step: Duration, unevenness: f32, stride: f32) -> Self {
+        Self::new(
+            step,
+            [
+                RhythmStep {
+                    length: 1.0 + unevenness,
+                    offset: Vec2::new(-stride / 2.0, 0.0),
+                    ..Default::default()
+                },
+                RhythmStep {
+                    length: 1.0 - unevenness,
+                    volume: 1.0 - unevenness,
+                    offset: Vec2::new(stride / 2.0, 0.0),
+                    ..Default::default()
+                },
+            ],
+        )
+    }
+
+    pub fn with_swing(mut self, swing: f32) -> Self {
+        self.swing = swing.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_humanize(mut self, humanize: f32) -> Self {
+        self.humanize = humanize.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The duration from step `index` to the next, which is never less than [`MIN_STEP_DURATION`].
+    pub fn step_duration(&self, index: usize) -> Duration {
+        let Some(step) = self.steps.get(index % self.steps.len().max(1)) else {
+            return self.unit.max(MIN_STEP_DURATION);
+        };
+
+        // Swing lengthens on-beats and shortens off-beats, so pairs keep their total.
+        let swing = if index % 2 == 0 {
+            1.0 + self.swing
+        } else {
+            1.0 - self.swing
+        };
+
+        let humanize = if self.humanize > 0.0 {
+            rand::thread_rng().gen_range(-self.humanize..=self.humanize)
+        } else {
+            0.0
+        };
+
+        self.unit
+            .mul_f32((step.length * swing * (1.0 + humanize)).max(0.0))
+            .max(MIN_STEP_DURATION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNIT: Duration = Duration::from_millis(200);
+
+    fn assert_close(actual: Duration, expected: Duration) {
+        let difference = actual.abs_diff(expected);
+        assert!(
+            difference < Duration::from_micros(10),
+            "expected {expected:?}, got {actual:?}"
+        );
+    }
+
+    #[test]
+    fn steps_scale_the_unit() {
+        let pattern = RhythmPattern::new(
+            UNIT,
+            [
+                RhythmStep::default(),
+                RhythmStep {
+                    length: 0.5,
+                    ..Default::default()
+                },
+            ],
+        );
+
+        assert_close(pattern.step_duration(0), UNIT);
+        assert_close(pattern.step_duration(1), UNIT / 2);
+        assert_close(pattern.step_duration(2), UNIT);
+    }
+
+    #[test]
+    fn swing_lengthens_even_steps() {
+        let pattern = RhythmPattern::accents(UNIT, &[1.0, 1.0]).with_swing(0.5);
+
+        assert_close(pattern.step_duration(0), UNIT.mul_f32(1.5));
+        assert_close(pattern.step_duration(1), UNIT.mul_f32(0.5));
+        assert_close(
+            pattern.step_duration(0) + pattern.step_duration(1),
+            UNIT * 2,
+        );
+    }
+
+    #[test]
+    fn rests_keep_their_length() {
+        let pattern = RhythmPattern::new(UNIT, [RhythmStep::rest(2.0)]);
+
+        assert_close(pattern.step_duration(0), UNIT * 2);
+    }
+
+    #[test]
+    fn empty_steps_take_the_minimum() {
+        let zero_length = RhythmPattern::new(UNIT, [RhythmStep::rest(0.0)]);
+        let zero_unit = RhythmPattern::accents(Duration::ZERO, &[1.0]);
+        let full_swing = RhythmPattern::gait(UNIT, 0.0, 1.0).with_swing(1.0);
+
+        assert_eq!(zero_length.step_duration(0), MIN_STEP_DURATION);
+        assert_eq!(zero_unit.step_duration(0), MIN_STEP_DURATION);
+        assert_eq!(full_swing.step_duration(1), MIN_STEP_DURATION);
+    }
+
+    #[test]
+    fn patterns_without_steps_use_the_unit() {
+        let pattern = RhythmPattern::new(UNIT, []);
+
+        assert_eq!(pattern.step_duration(3), UNIT);
+    }
+}
@@ -0,0 +1,170 @@
+//! Samples as Bevy assets.
+//!
+//! Audio files load through the [`AssetServer`] as [`AudioSample`]s,
+//! decoded with `symphonium` and resampled to the engine's output rate.
+//! What's decoded is kept in the [`SampleCache`] for the next launch.
+//! Both engines play straight from the decoded channels, so a sample
+//! only lives in memory once, for as long as something holds its handle.
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, io::Reader},
+    prelude::*,
+};
+use std::{io::Cursor, path::PathBuf, sync::Arc};
+
+use crate::audio::{
+    cache::{CacheKey, CachedSample, SampleCache},
+    loops::{LoopFrames, find_loop_region},
+    loudness::{self, Normalization},
+};
+
+pub fn sample_plugin(app: &mut App) {
+    app.init_asset::<AudioSample>();
+}
+
+/// The file extensions decoded as samples.
+pub const AUDIO_EXTENSIONS: &[&str] = &["wav", "ogg", "flac", "mp3"];
+
+/// A decoded sample, resampled to the engine's rate.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct AudioSample {
+    /// The sample's data, one buffer per channel.
+    pub channels: Arc<[Vec<f32>]>,
+    pub sample_rate: u32,
+    /// The repeating part of the sample, if it has one.
+    pub loop_frames: Option<LoopFrames>,
+    /// The sample's [`Normalization`] gain, applied by engines on top of the event's volume.
+    pub gain: f32,
+}
+
+impl AudioSample {
+    pub fn num_channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn frames(&self) -> usize {
+        self.channels.first().map(Vec::len).unwrap_or(0)
+    }
+}
+
+/// Decodes [`AudioSample`]s, resampling them to the engine's output.
+///
+/// Engines register this once they know their sample rate.
+pub struct AudioSampleLoader {
+    pub sample_rate: u32,
+    pub normalization: Normalization,
+    pub cache: SampleCache,
+}
+
+#[derive(Debug)]
+pub enum AudioSampleError {
+    Io(std::io::Error),
+    Decode(String),
+}
+
+impl core::fmt::Display for AudioSampleError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            AudioSampleError::Io(e) => write!(f, "failed to read sample: {e}"),
+            AudioSampleError::Decode(e) => write!(f, "failed to decode sample: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for AudioSampleError {}
+
+impl From<std::io::Error> for AudioSampleError {
+    fn from(e: std::io::Error) -> Self {
+        AudioSampleError::Io(e)
+    }
+}
+
+impl AssetLoader for AudioSampleLoader {
+    type Asset = AudioSample;
+    type Settings = ();
+    type Error = AudioSampleError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<AudioSample, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        // Most samples don't have a sidecar, so a failed read just means there isn't one.
+        let mut sidecar_path = load_context.path().as_os_str().to_owned();
+        sidecar_path.push(".loop");
+        let sidecar = load_context
+            .read_asset_bytes(PathBuf::from(sidecar_path))
+            .await
+            .ok()
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+
+        let region = find_loop_region(&bytes, sidecar.as_deref()).unwrap_or_else(|_| {
+            warn!(
+                "ignoring malformed loop file for {}",
+                load_context.path().display()
+            );
+            None
+        });
+
+        let path = load_context.path().to_string_lossy().into_owned();
+        let key = CacheKey::new(&path, &bytes, self.sample_rate);
+
+        let (mut entry, cached) = match self.cache.read(&key) {
+            Some(entry) => (entry, true),
+            None => {
+                let data = symphonium::SymphoniumLoader::new()
+                    .load_f32_from_source(
+                        Box::new(Cursor::new(bytes)),
+                        None,
+                        Some(self.sample_rate),
+                        Default::default(),
+                        None,
+                    )
+                    .map_err(|e| AudioSampleError::Decode(e.to_string()))?;
+
+                let entry = CachedSample {
+                    sample_rate: data.sample_rate,
+                    channels: data.data,
+                    loudness: None,
+                };
+
+                (entry, false)
+            }
+        };
+
+        let frames = entry.channels.first().map(Vec::len).unwrap_or(0);
+        let loop_frames = region.and_then(|region| region.frames(entry.sample_rate, frames as u64));
+
+        // Loudness only depends on the file, so it's cached along with the decoded data.
+        let mut measured = false;
+        let loudness = if self.normalization.measures(&path) {
+            let loudness = *entry.loudness.get_or_insert_with(|| {
+                measured = true;
+                loudness::measure(&entry.channels, entry.sample_rate)
+            });
+            self.normalization.remember(&path, loudness);
+            loudness
+        } else {
+            None
+        };
+
+        if !cached || measured {
+            self.cache.write(&key, &entry);
+        }
+
+        Ok(AudioSample {
+            gain: self.normalization.gain(&path, loudness),
+            sample_rate: entry.sample_rate,
+            channels: entry.channels.into(),
+            loop_frames,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        AUDIO_EXTENSIONS
+    }
+}
@@ -0,0 +1,283 @@
+//! Layered music, where synchronized stems fade in and out
+//! with a single intensity parameter.
+//!
+//! Engines play every stem of a [`LayeredAudioEvent`] from the
+//! same sample and only expose per-stem gains, so the layers
+//! can't drift apart.
+
+use bevy::prelude::*;
+
+use crate::audio::{clock::AudioInstant, sample::AudioSample};
+
+pub fn stems_plugin(app: &mut App) {
+    app.init_resource::<MusicIntensity>()
+        .add_systems(Update, (ease_intensity, apply_intensity).chain())
+        .add_observer(observe_intensity_event);
+}
+
+/// Queue a set of stems that play in lockstep.
+///
+/// The engine spawns a single entity for the group, with
+/// a [`StemGains`] component controlling each layer.
+#[derive(Debug, Event, Clone)]
+pub struct LayeredAudioEvent {
+    pub stems: &'static [MusicStem],
+    pub speed: f32,
+    pub volume: f32,
+    pub looping: bool,
+    pub name: Option<&'static str>,
+    /// When to start playback. Engines pick a shared start time if this is `None`.
+    pub start: Option<AudioInstant>,
+}
+
+impl Default for LayeredAudioEvent {
+    fn default() -> Self {
+        Self {
+            stems: &[],
+            speed: 1.0,
+            volume: 1.0,
+            looping: false,
+            name: None,
+            start: None,
+        }
+    }
+}
+
+/// A single layer of a piece of music.
+#[derive(Debug, Clone, Copy)]
+pub struct MusicStem {
+    pub sample: &'static str,
+    /// The intensity at which the stem begins to fade in.
+    ///
+    /// A threshold of zero means the stem is always audible.
+    pub threshold: f32,
+    pub volume: f32,
+}
+
+/// Make sure a set of stems can play together.
+///
+/// Stems loop independently, so any difference in channels, length,
+/// or sample rate would pull them apart a little more on each loop.
+pub fn check_stems(stems: &[MusicStem], samples: &[&AudioSample]) -> Result {
+    let Some((first_stem, first)) = stems.first().zip(samples.first()) else {
+        return Ok(());
+    };
+
+    for (stem, sample) in stems.iter().zip(samples).skip(1) {
+        let mismatch = if sample.num_channels() != first.num_channels() {
+            "channel count"
+        } else if sample.sample_rate != first.sample_rate {
+            "sample rate"
+        } else if sample.frames() != first.frames() {
+            "length"
+        } else {
+            continue;
+        };
+
+        return Err(format!(
+            "stem \"{}\" doesn't match the {mismatch} of \"{}\"",
+            stem.sample, first_stem.sample
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// The linear gain of each stem in a layered voice.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct StemGains(pub Vec<f32>);
+
+impl StemGains {
+    pub fn at(stems: &[MusicStem], intensity: f32) -> Self {
+        Self(stems.iter().map(|s| stem_gain(s, intensity)).collect())
+    }
+}
+
+/// The stems belonging to a layered voice.
+///
+/// Engines insert this alongside [`StemGains`].
+#[derive(Component, Debug, Clone)]
+pub struct Stems(pub &'static [MusicStem]);
+
+/// How much of an intensity range each stem takes to fade in.
+const STEM_FADE_RANGE: f32 = 0.15;
+
+/// The music's current intensity, from 0 to 1.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct MusicIntensity {
+    pub current: f32,
+    pub target: f32,
+    /// How quickly `current` approaches `target`, in units per second.
+    pub rate: f32,
+}
+
+impl Default for MusicIntensity {
+    fn default() -> Self {
+        Self {
+            current: 0.0,
+            target: 0.0,
+            rate: 0.25,
+        }
+    }
+}
+
+/// Move the music intensity towards a new target.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct IntensityEvent {
+    pub target: f32,
+    /// The time it takes to cover the full intensity range.
+    pub seconds: f32,
+}
+
+fn observe_intensity_event(
+    trigger: Trigger<IntensityEvent>,
+    mut intensity: ResMut<MusicIntensity>,
+) {
+    intensity.target = trigger.target.clamp(0.0, 1.0);
+    intensity.rate = trigger.seconds.max(f32::EPSILON).recip();
+}
+
+impl MusicIntensity {
+    /// Move `current` towards `target` for the given time, without overshooting.
+    pub fn advance(&mut self, seconds: f32) {
+        let step = self.rate * seconds;
+        let difference = self.target - self.current;
+
+        self.current += difference.clamp(-step, step);
+    }
+}
+
+fn ease_intensity(mut intensity: ResMut<MusicIntensity>, time: Res<Time>) {
+    if intensity.current == intensity.target {
+        return;
+    }
+
+    intensity.advance(time.delta_secs());
+}
+
+/// The gain of a stem at the given intensity.
+pub fn stem_gain(stem: &MusicStem, intensity: f32) -> f32 {
+    if stem.threshold <= 0.0 {
+        return stem.volume;
+    }
+
+    let fade = ((intensity - stem.threshold) / STEM_FADE_RANGE).clamp(0.0, 1.0);
+    stem.volume * fade * fade
+}
+
+fn apply_intensity(mut voices: Query<(&Stems, &mut StemGains)>, intensity: Res<MusicIntensity>) {
+    for (stems, mut gains) in &mut voices {
+        gains.set_if_neq(StemGains::at(stems.0, intensity.current));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: MusicStem = MusicStem {
+        sample: "base.ogg",
+        threshold: 0.0,
+        volume: 0.8,
+    };
+
+    const LAYER: MusicStem = MusicStem {
+        sample: "layer.ogg",
+        threshold: 0.5,
+        volume: 0.6,
+    };
+
+    fn sample(channels: usize, frames: usize, sample_rate: u32) -> AudioSample {
+        AudioSample {
+            channels: vec![vec![0.0; frames]; channels].into(),
+            sample_rate,
+            loop_frames: None,
+            gain: 1.0,
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn base_stems_are_always_at_full_volume() {
+        for intensity in [0.0, 0.5, 1.0] {
+            assert_eq!(stem_gain(&BASE, intensity), BASE.volume);
+        }
+    }
+
+    #[test]
+    fn layers_are_silent_at_rest() {
+        assert_eq!(stem_gain(&LAYER, 0.0), 0.0);
+        assert_eq!(stem_gain(&LAYER, LAYER.threshold), 0.0);
+    }
+
+    #[test]
+    fn layers_are_at_full_volume_at_peak_intensity() {
+        assert!(close(stem_gain(&LAYER, 1.0), LAYER.volume));
+        assert!(close(
+            stem_gain(&LAYER, LAYER.threshold + STEM_FADE_RANGE),
+            LAYER.volume
+        ));
+    }
+
+    #[test]
+    fn layers_fade_in_over_the_crossover() {
+        // Halfway through the fade, the gain is a quarter of the volume.
+        let halfway = LAYER.threshold + STEM_FADE_RANGE / 2.0;
+
+        assert!(close(stem_gain(&LAYER, halfway), LAYER.volume * 0.25));
+        assert_eq!(
+            StemGains::at(&[BASE, LAYER], halfway),
+            StemGains(vec![BASE.volume, LAYER.volume * 0.25])
+        );
+    }
+
+    #[test]
+    fn intensity_eases_without_overshooting() {
+        let mut intensity = MusicIntensity {
+            current: 0.0,
+            target: 1.0,
+            rate: 0.5,
+        };
+
+        intensity.advance(1.0);
+        assert!(close(intensity.current, 0.5));
+
+        intensity.advance(2.0);
+        assert_eq!(intensity.current, 1.0);
+
+        intensity.target = 0.25;
+        intensity.advance(1.0);
+        assert!(close(intensity.current, 0.5));
+
+        intensity.advance(1.0);
+        assert_eq!(intensity.current, 0.25);
+    }
+
+    #[test]
+    fn matching_stems_can_play_together() {
+        let samples = [sample(2, 100, 48_000), sample(2, 100, 48_000)];
+
+        assert!(check_stems(&[BASE, LAYER], &[&samples[0], &samples[1]]).is_ok());
+    }
+
+    #[test]
+    fn mismatched_stems_are_rejected() {
+        let first = sample(2, 100, 48_000);
+
+        for (other, mismatch) in [
+            (sample(2, 99, 48_000), "length"),
+            (sample(1, 100, 48_000), "channel count"),
+            (sample(2, 100, 44_100), "sample rate"),
+        ] {
+            let error = check_stems(&[BASE, LAYER], &[&first, &other]).unwrap_err();
+            assert!(
+                error.to_string().contains(mismatch),
+                "expected a {mismatch} mismatch, got: {error}"
+            );
+        }
+    }
+}
@@ -0,0 +1,578 @@
+//! Streaming playback for long samples.
+//!
+//! Music and ambience beds can run for minutes, and decoding them up
+//! front holds every frame in memory. A [`SampleStream`] instead decodes
+//! from disk on a background thread, a few seconds ahead of playback,
+//! into a ring buffer that the engine drains as it plays.
+//!
+//! Samples are streamed when they're larger on disk than
+//! [`Streaming::threshold`], unless a [`StreamMode`] says otherwise.
+//! Streams only move forward, so samples with a `.loop` sidecar are
+//! decoded in full. Loops inside a WAV file's `smpl` chunk aren't
+//! checked for, so large looped WAV files should be set to
+//! [`StreamMode::Never`].
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use ringbuf::{
+    HeapCons, HeapProd, HeapRb,
+    traits::{Consumer, Observer, Producer, Split},
+};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{Decoder, DecoderOptions},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use crate::audio::{
+    loudness::{LoudnessMeter, Normalization},
+    report::{SampleFailure, SampleLoadError, SampleReport},
+};
+
+pub fn stream_plugin(app: &mut App) {
+    app.init_resource::<Streaming>()
+        .add_systems(Startup, measure_streams);
+}
+
+/// Whether a sample should be streamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamMode {
+    /// Stream the sample if it's larger than [`Streaming::threshold`].
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Decides which samples are streamed rather than decoded in full.
+#[derive(Resource, Debug, Clone)]
+pub struct Streaming {
+    /// Samples larger than this on disk are streamed, in bytes.
+    pub threshold: u64,
+    /// How far ahead of playback to decode, in seconds.
+    pub buffer_seconds: f32,
+    modes: HashMap<&'static str, StreamMode>,
+    /// Decisions already made, so each sample's file is only checked once.
+    decisions: Arc<Mutex<HashMap<String, bool>>>,
+    /// Streams that failed to open or decode, waiting to be reported.
+    failures: Arc<Mutex<Vec<SampleLoadError>>>,
+}
+
+impl Default for Streaming {
+    fn default() -> Self {
+        Self {
+            threshold: 512 * 1024,
+            buffer_seconds: 2.0,
+            modes: HashMap::default(),
+            decisions: Default::default(),
+            failures: Default::default(),
+        }
+    }
+}
+
+impl Streaming {
+    /// Override the threshold for a single sample.
+    pub fn set_mode(&mut self, sample: &'static str, mode: StreamMode) -> &mut Self {
+        self.modes.insert(sample, mode);
+        self.forget(sample);
+        self
+    }
+
+    /// Whether the sample at a path within `assets` should be streamed.
+    ///
+    /// The decision is made once, and kept until the sample is [forgotten](Self::forget).
+    pub fn should_stream(&self, sample: &str) -> bool {
+        let mut decisions = self.decisions.lock().unwrap();
+        if let Some(decision) = decisions.get(sample) {
+            return *decision;
+        }
+
+        let decision = self.decide(sample);
+        decisions.insert(sample.to_owned(), decision);
+
+        decision
+    }
+
+    /// Decide again the next time a sample plays, like after its file changes.
+    pub fn forget(&self, sample: &str) {
+        self.decisions.lock().unwrap().remove(sample);
+    }
+
+    /// Streams that have failed since the last call.
+    pub fn take_failures(&self) -> Vec<SampleLoadError> {
+        std::mem::take(&mut *self.failures.lock().unwrap())
+    }
+
+    fn record_failure(&self, error: SampleLoadError) {
+        self.failures.lock().unwrap().push(error);
+    }
+
+    fn decide(&self, sample: &str) -> bool {
+        let path = asset_path(sample);
+
+        match self.modes.get(sample).copied().unwrap_or_default() {
+            StreamMode::Always => true,
+            StreamMode::Never => false,
+            StreamMode::Auto => {
+                let mut sidecar = path.clone().into_os_string();
+                sidecar.push(".loop");
+
+                !Path::new(&sidecar).exists()
+                    && std::fs::metadata(&path).is_ok_and(|m| m.len() > self.threshold)
+            }
+        }
+    }
+}
+
+/// The path to a sample within `assets`.
+pub fn asset_path(sample: &str) -> PathBuf {
+    Path::new("assets").join(sample)
+}
+
+#[derive(Debug)]
+pub enum StreamError {
+    Io(std::io::Error),
+    Decode(SymphoniaError),
+    /// The file has no track that can be decoded.
+    NoTrack,
+}
+
+impl core::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            StreamError::Io(e) => write!(f, "failed to open stream: {e}"),
+            StreamError::Decode(e) => write!(f, "failed to decode stream: {e}"),
+            StreamError::NoTrack => write!(f, "failed to find a playable track"),
+        }
+    }
+}
+
+impl core::error::Error for StreamError {}
+
+impl From<std::io::Error> for StreamError {
+    fn from(e: std::io::Error) -> Self {
+        StreamError::Io(e)
+    }
+}
+
+impl From<SymphoniaError> for StreamError {
+    fn from(e: SymphoniaError) -> Self {
+        StreamError::Decode(e)
+    }
+}
+
+impl From<StreamError> for SampleFailure {
+    fn from(e: StreamError) -> Self {
+        match e {
+            StreamError::Io(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                SampleFailure::Missing
+            }
+            StreamError::Decode(SymphoniaError::Unsupported(_)) | StreamError::NoTrack => {
+                SampleFailure::NotAudio
+            }
+            e => SampleFailure::Decode(e.to_string()),
+        }
+    }
+}
+
+/// A sample decoding on a background thread.
+///
+/// Frames are interleaved and resampled to the engine's rate.
+/// Dropping the stream stops its thread.
+pub struct SampleStream {
+    consumer: HeapCons<f32>,
+    channels: usize,
+    len_frames: Option<u64>,
+    /// Set by the thread once it has nothing left to decode.
+    finished: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl SampleStream {
+    /// Start streaming a sample within `assets`.
+    ///
+    /// Looping streams start over at the end of the file, and never finish.
+    /// The stream applies its own [`Normalization`] gain. If the file hasn't
+    /// been measured yet, it plays as mastered while it's measured in the
+    /// background, then ramps to the gain once it's known.
+    ///
+    /// Failures to open or decode the file are also recorded
+    /// with [`Streaming`], so they reach the [`SampleReport`].
+    pub fn open(
+        sample: &str,
+        sample_rate: u32,
+        looping: bool,
+        streaming: &Streaming,
+        normalization: &Normalization,
+    ) -> Result<Self, SampleLoadError> {
+        let path = asset_path(sample);
+        let decoder = FileDecoder::open(&path).map_err(|e| {
+            let error = SampleLoadError::new(sample, SampleFailure::from(e));
+            streaming.record_failure(error.clone());
+            error
+        })?;
+
+        let channels = decoder.channels;
+        let ratio = decoder.sample_rate as f64 / sample_rate as f64;
+        let len_frames = decoder
+            .n_frames
+            .filter(|_| !looping)
+            .map(|frames| (frames as f64 / ratio).ceil() as u64);
+
+        let capacity = (streaming.buffer_seconds.max(0.1) * sample_rate as f32) as usize * channels;
+        let (producer, consumer) = HeapRb::<f32>::new(capacity).split();
+
+        let finished = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let thread = DecodeThread {
+            sample: sample.to_owned(),
+            normalization: normalization.clone(),
+            path,
+            channels,
+            ramp_frames: GAIN_RAMP_SECONDS * sample_rate as f32,
+            decoder,
+            looping,
+            resampler: LinearResampler::new(ratio, channels),
+            producer,
+            stopped: stopped.clone(),
+        };
+
+        let thread_finished = finished.clone();
+        let thread_sample = sample.to_owned();
+        let thread_streaming = streaming.clone();
+        std::thread::Builder::new()
+            .name(format!("stream {sample}"))
+            .spawn(move || {
+                if let Err(e) = thread.run() {
+                    thread_streaming.record_failure(SampleLoadError::new(
+                        thread_sample,
+                        SampleFailure::from(e),
+                    ));
+                }
+                thread_finished.store(true, Ordering::Release);
+            })
+            .map_err(|e| SampleLoadError::new(sample, SampleFailure::Decode(e.to_string())))?;
+
+        Ok(Self {
+            consumer,
+            channels,
+            len_frames,
+            finished,
+            stopped,
+        })
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.channels
+    }
+
+    /// The stream's length at the engine's rate, if it ends and the file says how long it is.
+    pub fn len_frames(&self) -> Option<u64> {
+        self.len_frames
+    }
+
+    /// Fill `samples` with whole, interleaved frames, returning how many frames were read.
+    ///
+    /// Reading less than asked for means the decoder has fallen behind, or the stream is over.
+    pub fn read(&mut self, samples: &mut [f32]) -> usize {
+        let frames = samples.len().min(self.consumer.occupied_len()) / self.channels;
+        self.consumer
+            .pop_slice(&mut samples[..frames * self.channels])
+            / self.channels
+    }
+
+    /// Whether every decoded frame has been read.
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire) && self.consumer.is_empty()
+    }
+}
+
+impl Drop for SampleStream {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+    }
+}
+
+/// How long a stream takes to reach its gain once it's measured, in seconds.
+const GAIN_RAMP_SECONDS: f32 = 0.5;
+
+/// The decoding half of a [`SampleStream`].
+struct DecodeThread {
+    sample: String,
+    normalization: Normalization,
+    path: PathBuf,
+    channels: usize,
+    /// The length of the ramp to a newly measured gain, in frames.
+    ramp_frames: f32,
+    decoder: FileDecoder,
+    looping: bool,
+    resampler: LinearResampler,
+    producer: HeapProd<f32>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl DecodeThread {
+    fn run(mut self) -> Result<(), StreamError> {
+        if self.normalization.measures(&self.sample) {
+            let path = self.path.clone();
+            self.normalization
+                .measure_in_background(&self.sample, move || measure_file(&path));
+        }
+
+        // Until the loudness is known, the stream plays as mastered.
+        let mut target = self.target_gain();
+        let mut gain = target.unwrap_or(1.0);
+        let mut slope = 0.0;
+
+        let mut resampled = Vec::new();
+
+        loop {
+            let Some(samples) = self.decoder.next_samples()? else {
+                if !self.looping {
+                    return Ok(());
+                }
+
+                self.decoder = FileDecoder::open(&self.path)?;
+                continue;
+            };
+
+            resampled.clear();
+            self.resampler.process(samples, &mut resampled);
+
+            if target.is_none() {
+                target = self.target_gain();
+                if let Some(target) = target {
+                    slope = (target - gain).abs() / self.ramp_frames.max(1.0);
+                }
+            }
+
+            let goal = target.unwrap_or(gain);
+            for frame in resampled.chunks_mut(self.channels.max(1)) {
+                gain += (goal - gain).clamp(-slope, slope);
+                for sample in frame {
+                    *sample *= gain;
+                }
+            }
+
+            let mut written = 0;
+            while written < resampled.len() {
+                if self.stopped.load(Ordering::Acquire) {
+                    return Ok(());
+                }
+
+                written += self.producer.push_slice(&resampled[written..]);
+                if written < resampled.len() {
+                    // The buffer is full, so playback is comfortably ahead.
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            }
+        }
+    }
+
+    /// The stream's gain, if it's known yet.
+    fn target_gain(&self) -> Option<f32> {
+        if !self.normalization.measures(&self.sample) {
+            return Some(self.normalization.gain(&self.sample, None));
+        }
+
+        self.normalization
+            .measured(&self.sample)
+            .map(|loudness| self.normalization.gain(&self.sample, loudness))
+    }
+}
+
+/// Make sure a streamed sample can be opened and has a track to decode.
+pub fn probe(sample: &str) -> Result<(), SampleLoadError> {
+    FileDecoder::open(&asset_path(sample))
+        .map(|_| ())
+        .map_err(|e| SampleLoadError::new(sample, SampleFailure::from(e)))
+}
+
+/// Measure a file's integrated loudness by decoding it from start to end.
+fn measure_file(path: &Path) -> Option<f32> {
+    let mut decoder = FileDecoder::open(path).ok()?;
+    let mut meter = LoudnessMeter::new(decoder.channels, decoder.sample_rate);
+
+    while let Ok(Some(samples)) = decoder.next_samples() {
+        meter.push_interleaved(samples);
+    }
+
+    meter.finish()
+}
+
+/// Measure every sample that will be streamed ahead of time, so
+/// streams rarely have to start at the wrong level.
+fn measure_streams(
+    streaming: Res<Streaming>,
+    normalization: Res<Normalization>,
+    report: Res<SampleReport>,
+) {
+    for sample in &report.audio {
+        if normalization.measures(sample) && streaming.should_stream(sample) {
+            let path = asset_path(sample);
+            normalization.measure_in_background(sample, move || measure_file(&path));
+        }
+    }
+}
+
+/// A file being decoded packet by packet.
+struct FileDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    channels: usize,
+    sample_rate: u32,
+    n_frames: Option<u64>,
+    buffer: Option<SampleBuffer<f32>>,
+}
+
+impl FileDecoder {
+    fn open(path: &Path) -> Result<Self, StreamError> {
+        let file = File::open(path)?;
+        let source = MediaSourceStream::new(Box::new(file), Default::default());
+
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(extension);
+        }
+
+        let probed = symphonia::default::get_probe().format(
+            &hint,
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
+
+        let format = probed.format;
+        let track = format.default_track().ok_or(StreamError::NoTrack)?;
+        let params = &track.codec_params;
+
+        let (Some(sample_rate), Some(channels)) = (params.sample_rate, params.channels) else {
+            return Err(StreamError::NoTrack);
+        };
+
+        let decoder = symphonia::default::get_codecs().make(params, &DecoderOptions::default())?;
+
+        Ok(Self {
+            track_id: track.id,
+            channels: channels.count(),
+            sample_rate,
+            n_frames: params.n_frames,
+            format,
+            decoder,
+            buffer: None,
+        })
+    }
+
+    /// Decode the next packet into interleaved samples, or `None` at the end of the file.
+    fn next_samples(&mut self) -> Result<Option<&[f32]>, StreamError> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt packet is skipped rather than ending the stream.
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let spec = *decoded.spec();
+            let frames = decoded.capacity() as u64;
+            let buffer = match &mut self.buffer {
+                Some(buffer) if buffer.capacity() >= frames as usize * spec.channels.count() => {
+                    buffer
+                }
+                buffer => buffer.insert(SampleBuffer::new(frames, spec)),
+            };
+
+            buffer.copy_interleaved_ref(decoded);
+
+            return Ok(Some(buffer.samples()));
+        }
+    }
+}
+
+/// Converts interleaved frames between rates, one chunk at a time.
+///
+/// Linear interpolation is plenty for the long, soft beds we stream,
+/// and it keeps the decoding thread cheap.
+struct LinearResampler {
+    /// Source frames per output frame.
+    ratio: f64,
+    channels: usize,
+    /// The position of the next output frame, where `0.0` is `previous`.
+    position: f64,
+    /// The last frame of the previous chunk.
+    previous: Vec<f32>,
+}
+
+impl LinearResampler {
+    fn new(ratio: f64, channels: usize) -> Self {
+        Self {
+            ratio,
+            channels,
+            position: 1.0,
+            previous: vec![0.0; channels],
+        }
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let frames = input.len() / self.channels;
+        if frames == 0 {
+            return;
+        }
+
+        if self.ratio == 1.0 {
+            output.extend_from_slice(input);
+            return;
+        }
+
+        // Frame `i` is `previous` for zero, and `input[i - 1]` after that.
+        let frame = |i: usize, channel: usize| match i {
+            0 => self.previous[channel],
+            i => input[(i - 1) * self.channels + channel],
+        };
+
+        while self.position < frames as f64 {
+            let index = self.position as usize;
+            let t = (self.position - index as f64) as f32;
+
+            for channel in 0..self.channels {
+                let a = frame(index, channel);
+                let b = frame(index + 1, channel);
+                output.push(a + (b - a) * t);
+            }
+
+            self.position += self.ratio;
+        }
+
+        self.position -= frames as f64;
+        self.previous
+            .copy_from_slice(&input[(frames - 1) * self.channels..frames * self.channels]);
+    }
+}
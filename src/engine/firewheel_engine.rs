@@ -1,50 +1,96 @@
 use bevy::{platform::collections::HashMap, prelude::*};
 use firewheel::{
     CpalConfig, FirewheelContext, Volume,
-    channel_config::NonZeroChannelCount,
+    channel_config::{ChannelCount, NonZeroChannelCount},
+    clock::{ClockSeconds, EventDelay},
     collector::ArcGc,
     diff::{Diff, Notify},
     nodes::{
+        fast_filters::lowpass::FastLowpassNode,
         sampler::{PlaybackState, RepeatMode, SamplerConfig, SamplerNode, SequenceType},
+        spatial_basic::SpatialBasicNode,
         volume::{VolumeNode, VolumeNodeConfig},
     },
     sample_resource::SampleResource,
-    sampler_pool::{FxChain, SamplerPool, SpatialBasicChain, WorkerID},
+    sampler_pool::{FxChain, SamplerPool, WorkerID},
+};
+use std::{
+    num::NonZeroUsize,
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
-use std::time::Duration;
-use walkdir::WalkDir;
 
-use crate::audio::{AudioEvent, VolumeFade};
+use crate::audio::{
+    AudioEvent, SpeedEvent, VoiceVolume, VolumeFade,
+    ambisonics::{AMBISONIC_CHANNELS, AmbisonicBed, DecodeMatrix, is_ambisonic},
+    cache::SampleCache,
+    clock::{AudioClock, AudioInstant, ScheduledStart},
+    loading::SampleLoader,
+    loops::{LoopFrames, LoopHandle, LoopPlayhead},
+    loudness::Normalization,
+    modal::{ChimeEvent, ModalChime},
+    occlusion::{EmitterPosition, OPEN_CUTOFF_HZ, Occlusion},
+    panning::{OutputLayout, SpeakerGains},
+    pools::SamplePools,
+    reload::{LoopingStream, LoopingVoice},
+    sample::{AudioSample, AudioSampleLoader},
+    stems::{LayeredAudioEvent, MusicIntensity, StemGains, Stems, check_stems},
+    stream::{SampleStream, Streaming},
+};
 
 pub struct FirewheelPlugin;
 
 impl Plugin for FirewheelPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(initialize_firewheel)
-            .add_systems(PreStartup, load_samples)
+            .init_resource::<SampleMap>()
+            .add_systems(PreUpdate, forget_samples)
+            .add_systems(First, update_clock)
             .add_systems(
                 Last,
                 (
                     monitor_workers,
                     apply_volume_fade,
                     apply_spatial_fade,
+                    apply_spatial_params,
+                    apply_ambisonic_fade,
+                    apply_decode_matrix,
+                    apply_stem_fade,
+                    apply_stem_gains,
                     update_firewheel,
                 )
                     .chain(),
             )
-            .add_observer(handle_sample_event);
+            .add_observer(handle_sample_event)
+            .add_observer(handle_chime_event)
+            .add_observer(handle_layered_event)
+            .add_observer(handle_speed_event)
+            .add_observer(stop_spatial_worker)
+            .add_observer(stop_volume_worker)
+            .add_observer(stop_ambisonic_worker)
+            .add_observer(stop_stem_workers);
     }
 }
 
 #[derive(Resource)]
-struct SpatialPool(SamplerPool<SpatialBasicChain>);
+struct SpatialPool(SamplerPool<SpatialChain>);
 
 #[derive(Resource)]
 struct VolumePool(SamplerPool<VolumeChain>);
 
+#[derive(Resource)]
+struct AmbisonicPool(SamplerPool<AmbisonicChain>);
+
 /// Here we initialize the Firewheel audio engine.
 fn initialize_firewheel(app: &mut App) {
-    let config = firewheel::FirewheelConfig::default();
+    let layout = app.world().resource::<OutputLayout>().0;
+    let output_channels = layout.channel_count() as u32;
+
+    let config = firewheel::FirewheelConfig {
+        num_graph_outputs: ChannelCount::new(output_channels).unwrap(),
+        ..Default::default()
+    };
     let stream_config = CpalConfig {
         output: firewheel::CpalOutputConfig {
             desired_block_frames: None,
@@ -62,7 +108,8 @@ fn initialize_firewheel(app: &mut App) {
         SamplerConfig::default(),
         // straight to the output
         context.graph_out_node_id(),
-        NonZeroChannelCount::STEREO,
+        // spatial voices are panned across every speaker
+        NonZeroChannelCount::new(output_channels).unwrap(),
         &mut context,
     );
 
@@ -75,143 +122,827 @@ fn initialize_firewheel(app: &mut App) {
         &mut context,
     );
 
-    app.insert_non_send_resource(context)
-        .insert_resource(VolumePool(basic))
-        .insert_resource(SpatialPool(spatial));
+    // Ambisonic samples carry a negated copy of each channel,
+    // since the decoder can only apply positive gains.
+    let ambisonic = SamplerPool::new(
+        4,
+        SamplerConfig {
+            channels: NonZeroChannelCount::new(2 * AMBISONIC_CHANNELS as u32).unwrap(),
+            ..Default::default()
+        },
+        context.graph_out_node_id(),
+        NonZeroChannelCount::new(output_channels).unwrap(),
+        &mut context,
+    );
+
+    let sample_rate = context.stream_info().unwrap().sample_rate.get();
+    let normalization = app.world().resource::<Normalization>().clone();
+    let cache = app.world().resource::<SampleCache>().clone();
+
+    app.register_asset_loader(AudioSampleLoader {
+        sample_rate,
+        normalization,
+        cache,
+    })
+    .insert_non_send_resource(context)
+    .insert_resource(VolumePool(basic))
+    .insert_resource(SpatialPool(spatial))
+    .insert_resource(AmbisonicPool(ambisonic));
 }
 
-fn load_samples(mut commands: Commands, context: NonSend<FirewheelContext>) -> Result {
-    let sample_rate = context.stream_info().unwrap().sample_rate;
-    let assets_path = std::path::Path::new("assets");
+/// Forget prepared samples whose assets change or go away.
+fn forget_samples(
+    mut events: EventReader<AssetEvent<AudioSample>>,
+    mut samples: ResMut<SampleMap>,
+) {
+    for event in events.read() {
+        match event {
+            AssetEvent::Modified { id }
+            | AssetEvent::Removed { id }
+            | AssetEvent::Unused { id } => {
+                samples.0.retain(|(sample, _), _| sample != id);
+            }
+            _ => {}
+        }
+    }
+}
 
-    let mut assets = HashMap::default();
-    for asset_entry in WalkDir::new(assets_path).into_iter().filter_map(|e| e.ok()) {
-        let string_name: String = asset_entry
-            .path()
-            .strip_prefix(assets_path)
-            .unwrap()
-            .to_string_lossy()
-            .into();
+/// Synchronize state with the context via message passing.
+fn update_firewheel(mut context: NonSendMut<FirewheelContext>) -> Result {
+    context.update().map_err(|e| format!("{e:#?}"))?;
 
-        let Ok(data) = symphonium::SymphoniumLoader::new().load_f32(
-            asset_entry.path(),
-            Some(sample_rate.get()),
-            Default::default(),
-            None,
-        ) else {
-            continue;
+    Ok(())
+}
+
+/// Firewheel's clock advances with each processed block,
+/// so it can be used directly.
+fn update_clock(context: NonSend<FirewheelContext>, mut clock: ResMut<AudioClock>) {
+    clock.now = AudioInstant(context.clock_now().0);
+}
+
+/// Samples prepared for Firewheel, built the first time each one plays.
+///
+/// Samples played as Ambisonics are prepared separately, since they present extra channels.
+#[derive(Resource, Default)]
+pub struct SampleMap(HashMap<(AssetId<AudioSample>, bool), PreparedSample>);
+
+pub struct PreparedSample {
+    resource: ArcGc<dyn SampleResource>,
+    looped: Option<LoopedSample>,
+    gain: f32,
+}
+
+impl SampleMap {
+    fn prepare(
+        &mut self,
+        handle: &Handle<AudioSample>,
+        assets: &Assets<AudioSample>,
+        ambisonic: bool,
+    ) -> Result<&PreparedSample> {
+        let id = (handle.id(), ambisonic);
+
+        if !self.0.contains_key(&id) {
+            let sample = assets
+                .get(handle.id())
+                .ok_or("sample finished loading, but isn't available")?;
+
+            let channels = SharedChannels {
+                channels: sample.channels.clone(),
+                mirrored: ambisonic,
+            };
+
+            let looped = sample.loop_frames.map(|frames| LoopedSample {
+                channels: channels.clone(),
+                frames,
+            });
+
+            let resource = DecodedResource(channels);
+            let resource = ArcGc::new_unsized(|| Arc::new(resource) as Arc<dyn SampleResource>);
+
+            self.0.insert(
+                id,
+                PreparedSample {
+                    resource,
+                    looped,
+                    gain: sample.gain,
+                },
+            );
+        }
+
+        Ok(&self.0[&id])
+    }
+}
+
+/// An asset's decoded channels, shared rather than copied.
+///
+/// Ambisonic samples are mirrored, presenting each channel a
+/// second time with its polarity flipped for the decoder.
+#[derive(Clone)]
+struct SharedChannels {
+    channels: Arc<[Vec<f32>]>,
+    mirrored: bool,
+}
+
+impl SharedChannels {
+    fn count(&self) -> usize {
+        if self.mirrored {
+            2 * self.channels.len()
+        } else {
+            self.channels.len()
+        }
+    }
+
+    fn frames(&self) -> usize {
+        self.channels.first().map(Vec::len).unwrap_or(0)
+    }
+
+    /// The value of a presented channel at `frame`, or silence past the end.
+    fn value(&self, channel: usize, frame: usize) -> f32 {
+        let source = self.channels.len();
+        let value = self.channels[channel % source]
+            .get(frame)
+            .copied()
+            .unwrap_or(0.0);
+
+        if channel >= source { -value } else { value }
+    }
+}
+
+/// A decoded sample, shared with its loop region if it has one.
+struct DecodedResource(SharedChannels);
+
+impl SampleResource for DecodedResource {
+    fn num_channels(&self) -> NonZeroUsize {
+        NonZeroUsize::new(self.0.count()).unwrap()
+    }
+
+    fn len_frames(&self) -> u64 {
+        self.0.frames() as u64
+    }
+
+    fn fill_buffers(
+        &self,
+        buffers: &mut [&mut [f32]],
+        buffer_range: Range<usize>,
+        start_frame: u64,
+    ) {
+        let start = start_frame as usize;
+
+        for (channel, buffer) in buffers.iter_mut().enumerate().take(self.0.count()) {
+            for (i, index) in buffer_range.clone().enumerate() {
+                buffer[index] = self.0.value(channel, start + i);
+            }
+        }
+    }
+}
+
+pub struct LoopedSample {
+    channels: SharedChannels,
+    frames: LoopFrames,
+}
+
+impl LoopedSample {
+    /// Build a resource for a single voice, controlled by `handle`.
+    fn resource(&self, handle: &LoopHandle) -> ArcGc<dyn SampleResource> {
+        let len = self.channels.frames() as u64;
+        let resource = LoopedResource {
+            channels: self.channels.clone(),
+            playhead: LoopPlayhead::new(self.frames, len, handle),
         };
 
-        let source = firewheel::sample_resource::decoded_f32_to_resource(data);
-        let sample = ArcGc::new_unsized(|| source);
+        ArcGc::new_unsized(|| Arc::new(resource) as Arc<dyn SampleResource>)
+    }
+}
+
+/// A sample that plays through its intro, loops until told
+/// to exit, and then plays its outro.
+///
+/// The sampler sees a practically endless sample, and the
+/// [`LoopPlayhead`] decides which frame each position maps to.
+struct LoopedResource {
+    channels: SharedChannels,
+    playhead: LoopPlayhead,
+}
+
+/// The length reported for looped resources, which is a few thousand years at 48kHz.
+const ENDLESS_FRAMES: u64 = 1 << 52;
 
-        assets.insert(string_name, sample);
+impl SampleResource for LoopedResource {
+    fn num_channels(&self) -> NonZeroUsize {
+        NonZeroUsize::new(self.channels.count()).unwrap()
     }
 
-    commands.insert_resource(SampleMap(assets));
+    fn len_frames(&self) -> u64 {
+        ENDLESS_FRAMES
+    }
 
-    Ok(())
+    fn fill_buffers(
+        &self,
+        buffers: &mut [&mut [f32]],
+        buffer_range: Range<usize>,
+        start_frame: u64,
+    ) {
+        for (i, index) in buffer_range.enumerate() {
+            let frame = self.playhead.source_frame(start_frame + i as u64);
+
+            for (channel, buffer) in buffers.iter_mut().enumerate().take(self.channels.count()) {
+                buffer[index] = frame.map_or(0.0, |f| self.channels.value(channel, f as usize));
+            }
+        }
+    }
 }
 
-/// Synchronize state with the context via message passing.
-fn update_firewheel(mut context: NonSendMut<FirewheelContext>) -> Result {
-    context.update().map_err(|e| format!("{e:#?}"))?;
+/// A [`SampleStream`] read by the sampler as it plays.
+///
+/// Ambisonic streams are given their negated channels here,
+/// just like prepared samples.
+struct StreamedResource {
+    reader: Mutex<StreamReader>,
+    channels: usize,
+    len_frames: u64,
+}
 
-    Ok(())
+struct StreamReader {
+    stream: SampleStream,
+    /// The next frame the stream will produce.
+    position: u64,
+    /// Whether the stream has produced anything yet.
+    started: bool,
+    scratch: Vec<f32>,
 }
 
-#[derive(Resource)]
-pub struct SampleMap(HashMap<String, ArcGc<dyn SampleResource>>);
+/// How many frames are read from a stream at a time.
+const STREAM_CHUNK_FRAMES: usize = 1024;
+
+impl StreamedResource {
+    fn new(stream: SampleStream, ambisonic: bool) -> ArcGc<dyn SampleResource> {
+        let source_channels = stream.num_channels();
+        let channels = if ambisonic {
+            2 * source_channels
+        } else {
+            source_channels
+        };
+
+        let resource = StreamedResource {
+            channels,
+            len_frames: stream.len_frames().unwrap_or(ENDLESS_FRAMES),
+            reader: Mutex::new(StreamReader {
+                scratch: vec![0.0; STREAM_CHUNK_FRAMES * source_channels],
+                stream,
+                position: 0,
+                started: false,
+            }),
+        };
+
+        ArcGc::new_unsized(|| Arc::new(resource) as Arc<dyn SampleResource>)
+    }
+}
+
+impl SampleResource for StreamedResource {
+    fn num_channels(&self) -> NonZeroUsize {
+        NonZeroUsize::new(self.channels).unwrap()
+    }
+
+    fn len_frames(&self) -> u64 {
+        self.len_frames
+    }
+
+    fn fill_buffers(
+        &self,
+        buffers: &mut [&mut [f32]],
+        buffer_range: Range<usize>,
+        start_frame: u64,
+    ) {
+        for buffer in buffers.iter_mut() {
+            buffer[buffer_range.clone()].fill(0.0);
+        }
+
+        // Only the sampler reads from the stream, so the lock is never contended.
+        let Ok(mut reader) = self.reader.try_lock() else {
+            return;
+        };
+        let reader = &mut *reader;
+        let source_channels = reader.stream.num_channels();
+
+        // Time spent waiting for the first frames shouldn't be skipped.
+        if !reader.started {
+            reader.position = start_frame;
+        }
+
+        // Streams only move forward, so frames the sampler passed over are dropped.
+        while reader.position < start_frame {
+            let skip = ((start_frame - reader.position) as usize).min(STREAM_CHUNK_FRAMES);
+            let read = reader
+                .stream
+                .read(&mut reader.scratch[..skip * source_channels]);
+
+            if read == 0 {
+                break;
+            }
+            reader.position += read as u64;
+        }
+
+        let frames = buffer_range.len();
+        let mut written = 0;
+        while written < frames {
+            let chunk = (frames - written).min(STREAM_CHUNK_FRAMES);
+            let read = reader
+                .stream
+                .read(&mut reader.scratch[..chunk * source_channels]);
+
+            for (channel, buffer) in buffers.iter_mut().enumerate() {
+                let sign = if channel < source_channels { 1.0 } else { -1.0 };
+                let channel = channel % source_channels;
+
+                for frame in 0..read {
+                    buffer[buffer_range.start + written + frame] =
+                        sign * reader.scratch[frame * source_channels + channel];
+                }
+            }
+
+            written += read;
+
+            // The decoder has fallen behind, so the rest is silence.
+            if read < chunk {
+                break;
+            }
+        }
+
+        // Frames lost to an underrun are skipped on the next read, keeping the stream in time.
+        reader.position += written as u64;
+        reader.started |= written > 0;
+    }
+}
+
+/// A [`ModalChime`] rendered on the fly.
+struct ModalResource {
+    chime: ModalChime,
+    sample_rate: u32,
+}
+
+impl SampleResource for ModalResource {
+    fn num_channels(&self) -> NonZeroUsize {
+        NonZeroUsize::MIN
+    }
+
+    fn len_frames(&self) -> u64 {
+        self.chime.len_frames(self.sample_rate)
+    }
+
+    fn fill_buffers(
+        &self,
+        buffers: &mut [&mut [f32]],
+        buffer_range: Range<usize>,
+        start_frame: u64,
+    ) {
+        for (i, index) in buffer_range.enumerate() {
+            let value = self
+                .chime
+                .value_at(start_frame + i as u64, self.sample_rate);
+
+            for buffer in buffers.iter_mut() {
+                buffer[index] = value;
+            }
+        }
+    }
+}
 
 fn handle_sample_event(
     trigger: Trigger<AudioEvent>,
     mut spatial: ResMut<SpatialPool>,
     mut basic: ResMut<VolumePool>,
+    mut ambisonic_pool: ResMut<AmbisonicPool>,
     mut context: NonSendMut<FirewheelContext>,
-    samples: Res<SampleMap>,
+    mut samples: ResMut<SampleMap>,
+    assets: Res<Assets<AudioSample>>,
+    server: Res<AssetServer>,
+    mut pools: ResMut<SamplePools>,
+    mut loader: ResMut<SampleLoader>,
+    streaming: Res<Streaming>,
+    normalization: Res<Normalization>,
     mut commands: Commands,
 ) -> Result {
-    let repeat_mode = if trigger.looping {
-        RepeatMode::RepeatEndlessly
-    } else {
-        RepeatMode::PlayOnce
-    };
+    let trigger = pools.resolve(&trigger);
 
-    let sample = samples
-        .0
-        .get(trigger.sample)
-        .cloned()
-        .ok_or_else(|| format!("queued unknown sample {}", trigger.sample))?;
+    let mut loop_handle = None;
+    let mut looping_voice = None;
+    let mut looping_stream = None;
+    let mut ambisonic = false;
 
-    let params = SamplerNode {
-        sequence: Notify::new(Some(SequenceType::SingleSample {
+    // Streams repeat by decoding the file again, so they're played once.
+    // They also apply their own gain, since it may not be measured yet.
+    let (sample, repeat_mode, gain) =
+        if trigger.handle.is_none() && streaming.should_stream(trigger.sample) {
+            // Failures are recorded in the report, so there's nothing more to say here.
+            let Ok(stream) = SampleStream::open(
+                trigger.sample,
+                context.stream_info().unwrap().sample_rate.get(),
+                trigger.looping,
+                &streaming,
+                &normalization,
+            ) else {
+                return Ok(());
+            };
+
+            ambisonic = is_ambisonic(&trigger, stream.num_channels());
+
+            if trigger.looping {
+                looping_stream = Some(LoopingStream::new(trigger.clone()));
+            }
+
+            (
+                StreamedResource::new(stream, ambisonic),
+                RepeatMode::PlayOnce,
+                1.0,
+            )
+        } else {
+            let handle = loader.handle(&trigger, &server);
+            if !loader.ensure([&handle], &server)? {
+                loader.defer(trigger, vec![handle]);
+                return Ok(());
+            }
+
+            if trigger.looping {
+                looping_voice = Some(LoopingVoice::new(handle.clone(), trigger.clone()));
+            }
+
+            let channels = assets.get(&handle).map_or(0, AudioSample::num_channels);
+            ambisonic = is_ambisonic(&trigger, channels);
+
+            let prepared = samples.prepare(&handle, &assets, ambisonic)?;
+
+            match &prepared.looped {
+                // Looped resources handle repetition themselves.
+                Some(looped) if trigger.looping => {
+                    let handle = LoopHandle::default();
+                    let resource = looped.resource(&handle);
+                    loop_handle = Some(handle);
+
+                    (resource, RepeatMode::PlayOnce, prepared.gain)
+                }
+                _ => {
+                    let repeat_mode = if trigger.looping {
+                        RepeatMode::RepeatEndlessly
+                    } else {
+                        RepeatMode::PlayOnce
+                    };
+
+                    (prepared.resource.clone(), repeat_mode, prepared.gain)
+                }
+            }
+        };
+
+    let mut new_sound = spawn_voice(
+        Voice {
             sample,
-            volume: Volume::Linear(1.0),
             repeat_mode,
+            gain,
+            ambisonic,
+        },
+        &trigger,
+        &mut spatial,
+        &mut basic,
+        &mut ambisonic_pool,
+        &mut context,
+        &mut commands,
+    )?;
+
+    if let Some(handle) = loop_handle {
+        new_sound.insert(handle);
+    }
+
+    if let Some(voice) = looping_voice {
+        new_sound.insert(voice);
+    }
+
+    if let Some(stream) = looping_stream {
+        new_sound.insert(stream);
+    }
+
+    Ok(())
+}
+
+/// A sample resource ready to be played as a voice.
+struct Voice {
+    sample: ArcGc<dyn SampleResource>,
+    repeat_mode: RepeatMode,
+    gain: f32,
+    ambisonic: bool,
+}
+
+/// Play a voice through the right pool, placed and scheduled by the event.
+fn spawn_voice<'a>(
+    voice: Voice,
+    trigger: &AudioEvent,
+    spatial: &mut SpatialPool,
+    basic: &mut VolumePool,
+    ambisonic: &mut AmbisonicPool,
+    context: &mut FirewheelContext,
+    commands: &'a mut Commands,
+) -> Result<EntityCommands<'a>> {
+    let params = SamplerNode {
+        sequence: Notify::new(Some(SequenceType::SingleSample {
+            sample: voice.sample,
+            volume: Volume::Linear(voice.gain),
+            repeat_mode: voice.repeat_mode,
         })),
         speed: trigger.speed as f64,
-        playback: Notify::new(PlaybackState::Play { delay: None }),
+        playback: Notify::new(PlaybackState::Play {
+            delay: trigger
+                .start
+                .map(|start| EventDelay::DelayUntilSeconds(ClockSeconds(start.0))),
+        }),
         ..Default::default()
     };
 
     let mut new_sound = match trigger.position {
+        // Ambisonic beds surround the listener, so their position is ignored.
+        _ if voice.ambisonic => {
+            let worker = ambisonic.0.new_worker(&params, false, context, |_, _| {})?;
+
+            commands.spawn((
+                AmbisonicWorker {
+                    id: worker.worker_id,
+                    timer: Timer::new(Duration::from_millis(250), TimerMode::Once),
+                    volume: trigger.volume,
+                },
+                AmbisonicBed,
+                WorkerParams(params.clone()),
+            ))
+        }
         Some(position) => {
-            let worker =
-                spatial
-                    .0
-                    .new_worker(&params, false, &mut context, |fx_chain_state, cx| {
-                        let baseline = fx_chain_state.fx_chain.spatial_basic;
-
-                        fx_chain_state.fx_chain.spatial_basic.offset =
-                            Vec3::new(position.x, 0.0, position.y);
-                        fx_chain_state.fx_chain.spatial_basic.volume =
-                            Volume::Linear(trigger.volume);
-
-                        fx_chain_state.fx_chain.spatial_basic.diff(
-                            &baseline,
-                            Default::default(),
-                            &mut cx.event_queue(fx_chain_state.node_ids[0]),
-                        );
-                    })?;
-
-            commands.spawn(SpatialWorker {
-                id: worker.worker_id,
-                timer: Timer::new(Duration::from_millis(250), TimerMode::Once),
-            })
+            let worker = spatial
+                .0
+                .new_worker(&params, false, context, |fx_chain_state, cx| {
+                    // Surround voices are panned with `SpeakerGains` instead.
+                    if !fx_chain_state.fx_chain.speakers.is_empty() {
+                        return;
+                    }
+
+                    let baseline = fx_chain_state.fx_chain.spatial_basic;
+
+                    fx_chain_state.fx_chain.spatial_basic.offset =
+                        Vec3::new(position.x, 0.0, position.y);
+                    fx_chain_state.fx_chain.spatial_basic.volume = Volume::Linear(trigger.volume);
+
+                    fx_chain_state.fx_chain.spatial_basic.diff(
+                        &baseline,
+                        Default::default(),
+                        &mut cx.event_queue(fx_chain_state.node_ids[1]),
+                    );
+                })?;
+
+            commands.spawn((
+                SpatialWorker {
+                    id: worker.worker_id,
+                    timer: Timer::new(Duration::from_millis(250), TimerMode::Once),
+                    volume: trigger.volume,
+                },
+                EmitterPosition(position),
+                WorkerParams(params.clone()),
+            ))
         }
         None => {
-            let worker =
-                basic
-                    .0
-                    .new_worker(&params, true, &mut context, |fx_chain_state, cx| {
-                        let baseline = fx_chain_state.fx_chain.volume;
-                        fx_chain_state.fx_chain.volume.volume = Volume::Linear(trigger.volume);
-
-                        fx_chain_state.fx_chain.volume.diff(
-                            &baseline,
-                            Default::default(),
-                            &mut cx.event_queue(fx_chain_state.node_ids[0]),
-                        );
-                    })?;
-
-            commands.spawn(VolumeWorker {
-                id: worker.worker_id,
-                timer: Timer::new(Duration::from_millis(250), TimerMode::Once),
-            })
+            let worker = basic
+                .0
+                .new_worker(&params, true, context, |fx_chain_state, cx| {
+                    let baseline = fx_chain_state.fx_chain.volume;
+                    fx_chain_state.fx_chain.volume.volume = Volume::Linear(trigger.volume);
+
+                    fx_chain_state.fx_chain.volume.diff(
+                        &baseline,
+                        Default::default(),
+                        &mut cx.event_queue(fx_chain_state.node_ids[0]),
+                    );
+                })?;
+
+            commands.spawn((
+                VolumeWorker {
+                    id: worker.worker_id,
+                    timer: Timer::new(Duration::from_millis(250), TimerMode::Once),
+                },
+                WorkerParams(params.clone()),
+            ))
         }
     };
 
     if let Some(name) = trigger.name {
-        new_sound.insert(Name::new(name));
+        new_sound.insert((Name::new(name), VoiceVolume(trigger.volume)));
+    }
+
+    if let Some(start) = trigger.start {
+        new_sound.insert(ScheduledStart(start));
+    }
+
+    Ok(new_sound)
+}
+
+fn handle_chime_event(
+    trigger: Trigger<ChimeEvent>,
+    mut spatial: ResMut<SpatialPool>,
+    mut basic: ResMut<VolumePool>,
+    mut ambisonic: ResMut<AmbisonicPool>,
+    mut context: NonSendMut<FirewheelContext>,
+    mut commands: Commands,
+) -> Result {
+    let resource = ModalResource {
+        chime: trigger.chime,
+        sample_rate: context.stream_info().unwrap().sample_rate.get(),
+    };
+
+    spawn_voice(
+        Voice {
+            sample: ArcGc::new_unsized(|| Arc::new(resource) as Arc<dyn SampleResource>),
+            repeat_mode: RepeatMode::PlayOnce,
+            gain: 1.0,
+            ambisonic: false,
+        },
+        &trigger.event,
+        &mut spatial,
+        &mut basic,
+        &mut ambisonic,
+        &mut context,
+        &mut commands,
+    )?;
+
+    Ok(())
+}
+
+fn handle_layered_event(
+    trigger: Trigger<LayeredAudioEvent>,
+    mut basic: ResMut<VolumePool>,
+    mut context: NonSendMut<FirewheelContext>,
+    mut samples: ResMut<SampleMap>,
+    assets: Res<Assets<AudioSample>>,
+    server: Res<AssetServer>,
+    clock: Res<AudioClock>,
+    intensity: Res<MusicIntensity>,
+    mut loader: ResMut<SampleLoader>,
+    mut commands: Commands,
+) -> Result {
+    let handles: Vec<_> = trigger
+        .stems
+        .iter()
+        .map(|stem| loader.load(stem.sample, &server))
+        .collect();
+
+    if !loader.ensure(&handles, &server)? {
+        loader.defer(trigger.event().clone(), handles);
+        return Ok(());
+    }
+
+    let stem_samples = handles
+        .iter()
+        .map(|handle| assets.get(handle))
+        .collect::<Option<Vec<_>>>()
+        .ok_or("stems finished loading, but aren't available")?;
+    check_stems(trigger.stems, &stem_samples)?;
+
+    // Stems share the first one's normalization gain,
+    // so normalizing them can't change the balance between layers.
+    let shared_gain = stem_samples.first().map_or(1.0, |sample| sample.gain);
+
+    let repeat_mode = if trigger.looping {
+        RepeatMode::RepeatEndlessly
+    } else {
+        RepeatMode::PlayOnce
+    };
+
+    // Every stem must start on the same sample, so we
+    // always schedule them, even if no time was requested.
+    let start = trigger.start.unwrap_or(clock.horizon());
+    let gains = StemGains::at(trigger.stems, intensity.current);
+
+    let mut ids = Vec::new();
+    let mut stem_params = Vec::new();
+    for (handle, gain) in handles.iter().zip(gains.0.iter()) {
+        let prepared = samples.prepare(handle, &assets, false)?;
+
+        let params = SamplerNode {
+            sequence: Notify::new(Some(SequenceType::SingleSample {
+                sample: prepared.resource.clone(),
+                volume: Volume::Linear(shared_gain),
+                repeat_mode,
+            })),
+            speed: trigger.speed as f64,
+            playback: Notify::new(PlaybackState::Play {
+                delay: Some(EventDelay::DelayUntilSeconds(ClockSeconds(start.0))),
+            }),
+            ..Default::default()
+        };
+
+        let worker = basic
+            .0
+            .new_worker(&params, true, &mut context, |fx_chain_state, cx| {
+                let baseline = fx_chain_state.fx_chain.volume;
+                fx_chain_state.fx_chain.volume.volume = Volume::Linear(trigger.volume * gain);
+
+                fx_chain_state.fx_chain.volume.diff(
+                    &baseline,
+                    Default::default(),
+                    &mut cx.event_queue(fx_chain_state.node_ids[0]),
+                );
+            })?;
+
+        ids.push(worker.worker_id);
+        stem_params.push(params);
+    }
+
+    let mut new_sound = commands.spawn((
+        StemWorkers {
+            ids,
+            params: stem_params,
+            timer: Timer::new(Duration::from_millis(250), TimerMode::Once),
+            volume: trigger.volume,
+        },
+        Stems(trigger.stems),
+        gains,
+    ));
+
+    if let Some(name) = trigger.name {
+        new_sound.insert((Name::new(name), VoiceVolume(trigger.volume)));
     }
 
     Ok(())
 }
 
+/// Speed changes for every matching worker are queued in the
+/// same update, so they reach the audio thread together.
+fn handle_speed_event(
+    trigger: Trigger<SpeedEvent>,
+    mut voices: Query<(
+        &Name,
+        Option<&mut WorkerParams>,
+        Option<&mut StemWorkers>,
+        Option<&SpatialWorker>,
+        Option<&VolumeWorker>,
+        Option<&AmbisonicWorker>,
+    )>,
+    mut spatial: ResMut<SpatialPool>,
+    mut basic: ResMut<VolumePool>,
+    mut ambisonic: ResMut<AmbisonicPool>,
+    mut context: NonSendMut<FirewheelContext>,
+) {
+    let event_name = Name::new(trigger.name);
+    let speed = trigger.speed as f64;
+
+    for (name, params, stems, spatial_worker, volume_worker, ambisonic_worker) in &mut voices {
+        if name != &event_name {
+            continue;
+        }
+
+        if let Some(mut stems) = stems {
+            let StemWorkers { ids, params, .. } = &mut *stems;
+
+            for (id, params) in ids.iter().zip(params.iter_mut()) {
+                params.speed = speed;
+                basic.0.sync_worker_params(*id, params, &mut context);
+            }
+
+            continue;
+        }
+
+        let Some(mut params) = params else {
+            continue;
+        };
+        params.0.speed = speed;
+
+        if let Some(worker) = spatial_worker {
+            spatial
+                .0
+                .sync_worker_params(worker.id, &params.0, &mut context);
+        } else if let Some(worker) = volume_worker {
+            basic
+                .0
+                .sync_worker_params(worker.id, &params.0, &mut context);
+        } else if let Some(worker) = ambisonic_worker {
+            ambisonic
+                .0
+                .sync_worker_params(worker.id, &params.0, &mut context);
+        }
+    }
+}
+
+/// The sampler parameters a worker was started with, so they can be updated later.
+#[derive(Component)]
+struct WorkerParams(SamplerNode);
+
+/// A group of stems, each playing on its own worker in the [`VolumePool`].
+#[derive(Component)]
+struct StemWorkers {
+    ids: Vec<WorkerID>,
+    params: Vec<SamplerNode>,
+    timer: Timer,
+    /// The volume before the stem gains are applied.
+    volume: f32,
+}
+
 #[derive(Component)]
 struct SpatialWorker {
     id: WorkerID,
     timer: Timer,
+    /// The volume before occlusion is applied.
+    volume: f32,
 }
 
 #[derive(Component)]
@@ -220,13 +951,25 @@ struct VolumeWorker {
     timer: Timer,
 }
 
+#[derive(Component)]
+struct AmbisonicWorker {
+    id: WorkerID,
+    timer: Timer,
+    /// The volume before decoding.
+    volume: f32,
+}
+
 fn monitor_workers(
     mut spatial: Query<(Entity, &mut SpatialWorker)>,
     mut basic: Query<(Entity, &mut VolumeWorker)>,
+    mut ambisonic: Query<(Entity, &mut AmbisonicWorker)>,
+    mut stems: Query<(Entity, &mut StemWorkers)>,
+    looped: Query<(Entity, &LoopHandle)>,
 
-    mut spatial_pool: ResMut<SpatialPool>,
-    mut basic_pool: ResMut<VolumePool>,
-    mut context: NonSendMut<FirewheelContext>,
+    spatial_pool: Res<SpatialPool>,
+    basic_pool: Res<VolumePool>,
+    ambisonic_pool: Res<AmbisonicPool>,
+    context: NonSend<FirewheelContext>,
 
     time: Res<Time>,
     mut commands: Commands,
@@ -237,17 +980,92 @@ fn monitor_workers(
         // We allow each worker some time to flush its sequence to the audio graph.
         // This is handled much more robustly in `bevy_seedling`.
         if worker.timer.tick(delta).finished() && spatial_pool.0.stopped(worker.id, &context) {
-            spatial_pool.0.stop(worker.id, &mut context);
             commands.entity(entity).despawn();
         }
     }
 
     for (entity, mut worker) in &mut basic {
         if worker.timer.tick(delta).finished() && basic_pool.0.stopped(worker.id, &context) {
-            basic_pool.0.stop(worker.id, &mut context);
             commands.entity(entity).despawn();
         }
     }
+
+    for (entity, mut worker) in &mut ambisonic {
+        if worker.timer.tick(delta).finished() && ambisonic_pool.0.stopped(worker.id, &context) {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    for (entity, mut workers) in &mut stems {
+        if workers.timer.tick(delta).finished()
+            && workers
+                .ids
+                .iter()
+                .all(|id| basic_pool.0.stopped(*id, &context))
+        {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    // Looped resources never end on their own, so we stop them after the outro.
+    for (entity, handle) in &looped {
+        if handle.finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// Workers are stopped whenever their entity goes away, which lets
+// the engine-agnostic side stop a sound by despawning it.
+
+fn stop_spatial_worker(
+    trigger: Trigger<OnRemove, SpatialWorker>,
+    workers: Query<&SpatialWorker>,
+    mut pool: ResMut<SpatialPool>,
+    mut context: NonSendMut<FirewheelContext>,
+) -> Result {
+    let worker = workers.get(trigger.target())?;
+    pool.0.stop(worker.id, &mut context);
+
+    Ok(())
+}
+
+fn stop_volume_worker(
+    trigger: Trigger<OnRemove, VolumeWorker>,
+    workers: Query<&VolumeWorker>,
+    mut pool: ResMut<VolumePool>,
+    mut context: NonSendMut<FirewheelContext>,
+) -> Result {
+    let worker = workers.get(trigger.target())?;
+    pool.0.stop(worker.id, &mut context);
+
+    Ok(())
+}
+
+fn stop_ambisonic_worker(
+    trigger: Trigger<OnRemove, AmbisonicWorker>,
+    workers: Query<&AmbisonicWorker>,
+    mut pool: ResMut<AmbisonicPool>,
+    mut context: NonSendMut<FirewheelContext>,
+) -> Result {
+    let worker = workers.get(trigger.target())?;
+    pool.0.stop(worker.id, &mut context);
+
+    Ok(())
+}
+
+fn stop_stem_workers(
+    trigger: Trigger<OnRemove, StemWorkers>,
+    workers: Query<&StemWorkers>,
+    mut pool: ResMut<VolumePool>,
+    mut context: NonSendMut<FirewheelContext>,
+) -> Result {
+    let workers = workers.get(trigger.target())?;
+    for id in &workers.ids {
+        pool.0.stop(*id, &mut context);
+    }
+
+    Ok(())
 }
 
 #[derive(Default)]
@@ -289,6 +1107,126 @@ impl FxChain for VolumeChain {
     }
 }
 
+/// Firewheel's [`SpatialBasicChain`][firewheel::sampler_pool::SpatialBasicChain]
+/// with a low-pass filter in front, so occluded voices can be muffled.
+///
+/// When the output has more than two channels, the basic spatializer
+/// is replaced with one volume node per speaker, driven by [`SpeakerGains`].
+#[derive(Default)]
+struct SpatialChain {
+    lowpass: FastLowpassNode<2>,
+    spatial_basic: SpatialBasicNode,
+    speakers: Vec<VolumeNode>,
+}
+
+impl FxChain for SpatialChain {
+    fn construct_and_connect(
+        &mut self,
+        sampler_node_id: firewheel::node::NodeID,
+        _sampler_num_channels: NonZeroChannelCount,
+        dst_node_id: firewheel::node::NodeID,
+        dst_num_channels: NonZeroChannelCount,
+        cx: &mut FirewheelContext,
+    ) -> Vec<firewheel::node::NodeID> {
+        self.lowpass.cutoff_hz = OPEN_CUTOFF_HZ;
+
+        let lowpass_node = cx.add_node(self.lowpass, None);
+        cx.connect(sampler_node_id, lowpass_node, &[(0, 0), (1, 1)], true)
+            .unwrap();
+
+        let output_channels = dst_num_channels.get().get();
+        if output_channels <= 2 {
+            let spatial_node = cx.add_node(self.spatial_basic, None);
+
+            cx.connect(lowpass_node, spatial_node, &[(0, 0), (1, 1)], true)
+                .unwrap();
+            cx.connect(spatial_node, dst_node_id, &[(0, 0), (1, 1)], true)
+                .unwrap();
+
+            return vec![lowpass_node, spatial_node];
+        }
+
+        let mut node_ids = vec![lowpass_node];
+        for channel in 0..output_channels {
+            // Speakers stay silent until the first gains arrive.
+            let speaker = VolumeNode {
+                volume: Volume::Linear(0.0),
+                ..Default::default()
+            };
+
+            let speaker_node = cx.add_node(
+                speaker,
+                Some(VolumeNodeConfig {
+                    channels: NonZeroChannelCount::MONO,
+                    ..Default::default()
+                }),
+            );
+
+            // Both input channels are summed into a mono signal for panning,
+            // and scaled by `DOWNMIX_GAIN` to average them.
+            cx.connect(lowpass_node, speaker_node, &[(0, 0), (1, 0)], true)
+                .unwrap();
+            cx.connect(speaker_node, dst_node_id, &[(0, channel)], true)
+                .unwrap();
+
+            self.speakers.push(speaker);
+            node_ids.push(speaker_node);
+        }
+
+        node_ids
+    }
+}
+
+/// Decodes Ambisonics with one mono volume node per input and output channel pair.
+///
+/// The sampler provides each Ambisonic channel followed by its negation,
+/// so negative matrix weights are applied to the negated copy.
+#[derive(Default)]
+struct AmbisonicChain {
+    /// Indexed by `output * sampler_channels + input`.
+    decoders: Vec<VolumeNode>,
+}
+
+impl FxChain for AmbisonicChain {
+    fn construct_and_connect(
+        &mut self,
+        sampler_node_id: firewheel::node::NodeID,
+        sampler_num_channels: NonZeroChannelCount,
+        dst_node_id: firewheel::node::NodeID,
+        dst_num_channels: NonZeroChannelCount,
+        cx: &mut FirewheelContext,
+    ) -> Vec<firewheel::node::NodeID> {
+        let mut node_ids = Vec::new();
+
+        for output in 0..dst_num_channels.get().get() {
+            for input in 0..sampler_num_channels.get().get() {
+                let decoder = VolumeNode {
+                    volume: Volume::Linear(0.0),
+                    ..Default::default()
+                };
+
+                let decoder_node = cx.add_node(
+                    decoder,
+                    Some(VolumeNodeConfig {
+                        channels: NonZeroChannelCount::MONO,
+                        ..Default::default()
+                    }),
+                );
+
+                cx.connect(sampler_node_id, decoder_node, &[(input, 0)], true)
+                    .unwrap();
+                cx.connect(decoder_node, dst_node_id, &[(0, output)], true)
+                    .unwrap();
+
+                self.decoders.push(decoder);
+                node_ids.push(decoder_node);
+            }
+        }
+
+        node_ids
+    }
+}
+
 fn apply_volume_fade(
     mut workers: Query<(Entity, &VolumeWorker, &mut VolumeFade)>,
     mut pool: ResMut<VolumePool>,
@@ -314,7 +1252,7 @@ fn apply_volume_fade(
         );
 
         if fade.timer.finished() {
-            commands.entity(entity).remove::<VolumeFade>();
+            fade.finish(entity, &mut commands);
         }
     }
 
@@ -322,31 +1260,181 @@ fn apply_volume_fade(
 }
 
 fn apply_spatial_fade(
-    mut workers: Query<(Entity, &SpatialWorker, &mut VolumeFade)>,
-    mut pool: ResMut<SpatialPool>,
-    mut context: NonSendMut<FirewheelContext>,
+    mut workers: Query<(Entity, &mut SpatialWorker, &mut VolumeFade)>,
     mut commands: Commands,
     time: Res<Time>,
-) -> Result {
+) {
     let delta = time.delta();
-    for (entity, worker, mut fade) in &mut workers {
+    for (entity, mut worker, mut fade) in &mut workers {
         fade.timer.tick(delta);
         let elapsed = fade.timer.elapsed_secs() / fade.timer.duration().as_secs_f32();
 
-        let chain = pool.0.fx_chain_mut(worker.id).ok_or("invalid worker ID")?;
+        // The volume is written to the graph along with the occlusion.
+        worker.volume = fade.event.start.lerp(fade.event.end, elapsed);
 
-        let baseline = chain.fx_chain.spatial_basic;
-        chain.fx_chain.spatial_basic.volume =
-            Volume::Linear(fade.event.start.lerp(fade.event.end, elapsed));
+        if fade.timer.finished() {
+            fade.finish(entity, &mut commands);
+        }
+    }
+}
 
-        chain.fx_chain.spatial_basic.diff(
-            &baseline,
+/// Scales the summed left and right channels of a surround voice to their average,
+/// matching the rodio engine's downmix.
+const DOWNMIX_GAIN: f32 = 0.5;
+
+fn apply_spatial_params(
+    workers: Query<
+        (&SpatialWorker, &Occlusion, Option<&SpeakerGains>),
+        Or<(
+            Changed<SpatialWorker>,
+            Changed<Occlusion>,
+            Changed<SpeakerGains>,
+        )>,
+    >,
+    mut pool: ResMut<SpatialPool>,
+    mut context: NonSendMut<FirewheelContext>,
+) -> Result {
+    for (worker, occlusion, gains) in &workers {
+        let chain = pool.0.fx_chain_mut(worker.id).ok_or("invalid worker ID")?;
+        let volume = worker.volume * occlusion.gain;
+
+        let lowpass_baseline = chain.fx_chain.lowpass;
+        chain.fx_chain.lowpass.cutoff_hz = occlusion.cutoff_hz;
+        chain.fx_chain.lowpass.diff(
+            &lowpass_baseline,
             Default::default(),
             &mut context.event_queue(chain.node_ids[0]),
         );
 
+        if chain.fx_chain.speakers.is_empty() {
+            let spatial_baseline = chain.fx_chain.spatial_basic;
+            chain.fx_chain.spatial_basic.volume = Volume::Linear(volume);
+            chain.fx_chain.spatial_basic.diff(
+                &spatial_baseline,
+                Default::default(),
+                &mut context.event_queue(chain.node_ids[1]),
+            );
+
+            continue;
+        }
+
+        let Some(gains) = gains else {
+            continue;
+        };
+
+        for (i, (speaker, gain)) in chain
+            .fx_chain
+            .speakers
+            .iter_mut()
+            .zip(gains.0.iter())
+            .enumerate()
+        {
+            let baseline = *speaker;
+            speaker.volume = Volume::Linear(volume * gain * DOWNMIX_GAIN);
+            speaker.diff(
+                &baseline,
+                Default::default(),
+                &mut context.event_queue(chain.node_ids[i + 1]),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_ambisonic_fade(
+    mut workers: Query<(Entity, &mut AmbisonicWorker, &mut VolumeFade)>,
+    mut commands: Commands,
+    time: Res<Time>,
+) {
+    let delta = time.delta();
+    for (entity, mut worker, mut fade) in &mut workers {
+        fade.timer.tick(delta);
+        let elapsed = fade.timer.elapsed_secs() / fade.timer.duration().as_secs_f32();
+
+        // The volume is written to the graph along with the decoding matrix.
+        worker.volume = fade.event.start.lerp(fade.event.end, elapsed);
+
+        if fade.timer.finished() {
+            fade.finish(entity, &mut commands);
+        }
+    }
+}
+
+fn apply_decode_matrix(
+    workers: Query<
+        (&AmbisonicWorker, &DecodeMatrix),
+        Or<(Changed<AmbisonicWorker>, Changed<DecodeMatrix>)>,
+    >,
+    mut pool: ResMut<AmbisonicPool>,
+    mut context: NonSendMut<FirewheelContext>,
+) -> Result {
+    let sampler_channels = 2 * AMBISONIC_CHANNELS;
+
+    for (worker, matrix) in &workers {
+        let chain = pool.0.fx_chain_mut(worker.id).ok_or("invalid worker ID")?;
+
+        for (output, weights) in matrix.0.iter().enumerate() {
+            for (input, weight) in weights.iter().enumerate() {
+                let positive = output * sampler_channels + input;
+                let negative = positive + AMBISONIC_CHANNELS;
+
+                for (index, gain) in [(positive, weight.max(0.0)), (negative, (-weight).max(0.0))] {
+                    let Some(decoder) = chain.fx_chain.decoders.get_mut(index) else {
+                        continue;
+                    };
+
+                    let baseline = *decoder;
+                    decoder.volume = Volume::Linear(worker.volume * gain);
+                    decoder.diff(
+                        &baseline,
+                        Default::default(),
+                        &mut context.event_queue(chain.node_ids[index]),
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_stem_fade(
+    mut workers: Query<(Entity, &mut StemWorkers, &mut VolumeFade)>,
+    mut commands: Commands,
+    time: Res<Time>,
+) {
+    let delta = time.delta();
+    for (entity, mut workers, mut fade) in &mut workers {
+        fade.timer.tick(delta);
+        let elapsed = fade.timer.elapsed_secs() / fade.timer.duration().as_secs_f32();
+
+        // The volume is written to the graph along with the stem gains.
+        workers.volume = fade.event.start.lerp(fade.event.end, elapsed);
+
         if fade.timer.finished() {
-            commands.entity(entity).remove::<VolumeFade>();
+            fade.finish(entity, &mut commands);
+        }
+    }
+}
+
+fn apply_stem_gains(
+    workers: Query<(&StemWorkers, &StemGains), Or<(Changed<StemWorkers>, Changed<StemGains>)>>,
+    mut pool: ResMut<VolumePool>,
+    mut context: NonSendMut<FirewheelContext>,
+) -> Result {
+    for (workers, gains) in &workers {
+        for (id, gain) in workers.ids.iter().zip(gains.0.iter()) {
+            let chain = pool.0.fx_chain_mut(*id).ok_or("invalid worker ID")?;
+
+            let baseline = chain.fx_chain.volume;
+            chain.fx_chain.volume.volume = Volume::Linear(workers.volume * gain);
+
+            chain.fx_chain.volume.diff(
+                &baseline,
+                Default::default(),
+                &mut context.event_queue(chain.node_ids[0]),
+            );
         }
     }
 
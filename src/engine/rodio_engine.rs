@@ -1,19 +1,48 @@
 use bevy::{platform::collections::HashMap, prelude::*};
-use rodio::{
-    DeviceTrait, Sink, Source, SpatialSink, buffer::SamplesBuffer, cpal::traits::HostTrait,
+use rodio::{DeviceTrait, Sink, Source, SpatialSink, cpal::traits::HostTrait};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU32, AtomicU64, Ordering},
+    },
+    time::Duration,
 };
-use walkdir::WalkDir;
 
-use crate::audio::{AudioEvent, VolumeFade};
+use crate::audio::{
+    AudioEvent, SpeedEvent, VoiceVolume, VolumeFade,
+    ambisonics::{AMBISONIC_CHANNELS, AmbisonicBed, DecodeMatrix, is_ambisonic},
+    cache::SampleCache,
+    clock::{AudioClock, AudioInstant, ScheduledStart},
+    loading::SampleLoader,
+    loops::{LoopFrames, LoopHandle, LoopPlayhead},
+    loudness::Normalization,
+    modal::{ChimeEvent, ModalChime},
+    occlusion::{EmitterPosition, OPEN_CUTOFF_HZ, Occlusion},
+    panning::{OutputLayout, SpeakerGains},
+    pools::SamplePools,
+    reload::{LoopingStream, LoopingVoice},
+    sample::{AudioSample, AudioSampleLoader},
+    stems::{LayeredAudioEvent, MusicIntensity, StemGains, Stems, check_stems},
+    stream::{SampleStream, Streaming},
+};
 
 pub struct RodioPlugin;
 
 impl Plugin for RodioPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(initialize_rodio)
-            .add_systems(PreStartup, load_samples)
+            .init_resource::<SampleMap>()
+            .add_systems(PreUpdate, forget_samples)
+            .add_systems(First, update_clock)
             .add_systems(Update, (apply_fades, monitor_sinks).chain())
-            .add_observer(handle_sample_event);
+            .add_systems(
+                Last,
+                (apply_spatial_params, apply_decode_matrix, apply_stem_gains),
+            )
+            .add_observer(handle_sample_event)
+            .add_observer(handle_chime_event)
+            .add_observer(handle_layered_event)
+            .add_observer(handle_speed_event);
     }
 }
 
@@ -22,87 +51,892 @@ pub struct RodioStreamHandle(rodio::OutputStreamHandle);
 
 /// Here we initialize the rodio audio engine.
 fn initialize_rodio(app: &mut App) {
-    let (stream, stream_handle) = rodio::OutputStream::try_default().unwrap();
+    let layout = app.world().resource::<OutputLayout>().0;
+
+    let device = rodio::cpal::default_host()
+        .default_output_device()
+        .expect("unable to find default output device");
+    let default_config = device.default_output_config().unwrap();
+
+    let (stream, stream_handle) = if layout.is_surround() {
+        let config = rodio::SupportedStreamConfig::new(
+            layout.channel_count() as u16,
+            default_config.sample_rate(),
+            *default_config.buffer_size(),
+            default_config.sample_format(),
+        );
+
+        rodio::OutputStream::try_from_device_config(&device, config).unwrap()
+    } else {
+        rodio::OutputStream::try_default().unwrap()
+    };
 
-    app.insert_non_send_resource(stream)
-        .insert_resource(RodioStreamHandle(stream_handle));
+    let sample_rate = default_config.sample_rate().0;
+    let clock = RodioClock::new(&stream_handle, sample_rate).unwrap();
+
+    let normalization = app.world().resource::<Normalization>().clone();
+    let cache = app.world().resource::<SampleCache>().clone();
+
+    // We'll eagerly resample to match Firewheel.
+    app.register_asset_loader(AudioSampleLoader {
+        sample_rate,
+        normalization,
+        cache,
+    })
+    .insert_non_send_resource(stream)
+    .insert_resource(RodioStreamHandle(stream_handle))
+    .insert_resource(clock);
 }
 
-fn load_samples(mut commands: Commands) -> Result {
-    let sample_rate = rodio::cpal::default_host()
-        .default_output_device()
-        .ok_or("unable to find default output device")?
-        .default_output_config()?
-        .sample_rate();
-    let assets_path = std::path::Path::new("assets");
-
-    let mut assets = HashMap::default();
-
-    for asset_entry in WalkDir::new(assets_path).into_iter().filter_map(|e| e.ok()) {
-        let string_name: String = asset_entry
-            .path()
-            .strip_prefix(assets_path)
-            .unwrap()
-            .to_string_lossy()
-            .into();
-
-        // We'll eagerly decode and resample to match Firewheel.
-        let Ok(data) = symphonium::SymphoniumLoader::new().load_f32(
-            asset_entry.path(),
-            Some(sample_rate.0),
-            Default::default(),
-            None,
-        ) else {
-            continue;
-        };
+/// `rodio` has no global clock, so we count frames with
+/// a silent source that plays for the lifetime of the app.
+#[derive(Resource)]
+struct RodioClock {
+    frames: Arc<AtomicU64>,
+    sample_rate: u32,
+    _sink: Sink,
+}
+
+impl RodioClock {
+    fn new(
+        stream_handle: &rodio::OutputStreamHandle,
+        sample_rate: u32,
+    ) -> Result<Self, rodio::PlayError> {
+        let frames = Arc::new(AtomicU64::new(0));
+
+        let sink = Sink::try_new(stream_handle)?;
+        sink.append(ClockSource {
+            frames: frames.clone(),
+            sample_rate,
+        });
+
+        Ok(Self {
+            frames,
+            sample_rate,
+            _sink: sink,
+        })
+    }
+
+    fn now(&self) -> AudioInstant {
+        AudioInstant(self.frames.load(Ordering::Relaxed) as f64 / self.sample_rate as f64)
+    }
+
+    fn frame_at(&self, instant: AudioInstant) -> u64 {
+        (instant.0 * self.sample_rate as f64).round() as u64
+    }
+}
+
+/// A mono, silent source that counts every frame it produces.
+///
+/// Since it runs at the output sample rate, the mixer
+/// pulls exactly one sample from it per output frame.
+struct ClockSource {
+    frames: Arc<AtomicU64>,
+    sample_rate: u32,
+}
+
+impl Iterator for ClockSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        Some(0.0)
+    }
+}
+
+impl Source for ClockSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Outputs silence until the [`RodioClock`] reaches the start frame.
+struct Scheduled<S> {
+    input: S,
+    frames: Arc<AtomicU64>,
+    start_frame: u64,
+    started: bool,
+    channel: u16,
+}
+
+impl<S> Scheduled<S> {
+    fn new(input: S, clock: &RodioClock, start: AudioInstant) -> Self {
+        Self {
+            input,
+            frames: clock.frames.clone(),
+            start_frame: clock.frame_at(start),
+            started: false,
+            channel: 0,
+        }
+    }
+}
+
+impl<S> Iterator for Scheduled<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.started {
+            return self.input.next();
+        }
+
+        // We only start on a frame boundary so the channels stay in order.
+        if self.channel == 0 && self.frames.load(Ordering::Relaxed) >= self.start_frame {
+            self.started = true;
+            return self.input.next();
+        }
+
+        self.channel = (self.channel + 1) % self.input.channels();
+
+        Some(0.0)
+    }
+}
 
-        let mut buffer = vec![0.0; data.frames() * data.channels()];
+impl<S> Source for Scheduled<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
 
-        // interleave the buffer
-        for frame in 0..data.frames() {
-            for channel in 0..data.channels() {
-                let buffer_index = frame * data.channels() + channel;
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
 
-                buffer[buffer_index] = data.data[channel][frame];
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+fn update_clock(rodio_clock: Res<RodioClock>, mut clock: ResMut<AudioClock>) {
+    clock.now = rodio_clock.now();
+}
+
+/// Forget prepared samples whose assets change or go away.
+fn forget_samples(
+    mut events: EventReader<AssetEvent<AudioSample>>,
+    mut samples: ResMut<SampleMap>,
+) {
+    for event in events.read() {
+        match event {
+            AssetEvent::Modified { id }
+            | AssetEvent::Removed { id }
+            | AssetEvent::Unused { id } => {
+                samples.0.remove(id);
             }
+            _ => {}
         }
+    }
+}
 
-        assets.insert(
-            string_name,
-            SamplesBuffer::new(data.channels() as u16, data.sample_rate, buffer),
-        );
+/// Samples prepared for `rodio`, built the first time each one plays.
+#[derive(Resource, Default)]
+pub struct SampleMap(HashMap<AssetId<AudioSample>, PreparedSample>);
+
+pub struct PreparedSample {
+    /// The asset's decoded channels, shared by every voice.
+    channels: Arc<[Vec<f32>]>,
+    gain: f32,
+    sample_rate: u32,
+    loop_frames: Option<LoopFrames>,
+}
+
+impl PreparedSample {
+    fn num_channels(&self) -> u16 {
+        self.channels.len() as u16
     }
 
-    commands.insert_resource(SampleMap(assets));
+    /// A source that plays the sample through, or repeats it from the start.
+    fn source(&self, repeat: bool) -> SharedSource {
+        SharedSource {
+            channels: self.channels.clone(),
+            gain: self.gain,
+            sample_rate: self.sample_rate,
+            frame: 0,
+            channel: 0,
+            repeat,
+        }
+    }
+}
 
-    Ok(())
+impl SampleMap {
+    fn prepare(
+        &mut self,
+        handle: &Handle<AudioSample>,
+        assets: &Assets<AudioSample>,
+    ) -> Result<&PreparedSample> {
+        let id = handle.id();
+
+        if !self.0.contains_key(&id) {
+            let sample = assets
+                .get(id)
+                .ok_or("sample finished loading, but isn't available")?;
+
+            self.0.insert(
+                id,
+                PreparedSample {
+                    channels: sample.channels.clone(),
+                    gain: sample.gain,
+                    sample_rate: sample.sample_rate,
+                    loop_frames: sample.loop_frames,
+                },
+            );
+        }
+
+        Ok(&self.0[&id])
+    }
 }
 
-#[derive(Resource)]
-pub struct SampleMap(HashMap<String, SamplesBuffer<f32>>);
+/// Plays a [`PreparedSample`] straight from the asset's channels,
+/// interleaving them and applying the gain as it goes.
+///
+/// Repeating here, rather than with `repeat_infinite`,
+/// also avoids `rodio` buffering a copy of its own.
+struct SharedSource {
+    channels: Arc<[Vec<f32>]>,
+    gain: f32,
+    sample_rate: u32,
+    frame: usize,
+    channel: usize,
+    repeat: bool,
+}
+
+impl SharedSource {
+    fn frames(&self) -> usize {
+        self.channels.first().map(Vec::len).unwrap_or(0)
+    }
+}
+
+impl Iterator for SharedSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let frames = self.frames();
+        if self.frame >= frames {
+            if !self.repeat || frames == 0 {
+                return None;
+            }
+            self.frame = 0;
+        }
+
+        let sample = self.channels[self.channel][self.frame] * self.gain;
+
+        self.channel += 1;
+        if self.channel == self.channels.len() {
+            self.channel = 0;
+            self.frame += 1;
+        }
+
+        Some(sample)
+    }
+}
+
+impl Source for SharedSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        if self.repeat {
+            None
+        } else {
+            let remaining = self.frames().saturating_sub(self.frame) * self.channels.len();
+            Some(remaining.saturating_sub(self.channel))
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels.len() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        if self.repeat {
+            return None;
+        }
+
+        Some(Duration::from_secs_f64(
+            self.frames() as f64 / self.sample_rate as f64,
+        ))
+    }
+}
+
+/// Plays through a sample's intro, loops until told to exit,
+/// and then plays its outro.
+struct LoopedSource {
+    channels: Arc<[Vec<f32>]>,
+    gain: f32,
+    sample_rate: u32,
+    playhead: LoopPlayhead,
+    /// The playhead position in frames.
+    position: u64,
+    channel: usize,
+    frame: u64,
+}
+
+impl LoopedSource {
+    fn new(sample: &PreparedSample, frames: LoopFrames, handle: &LoopHandle) -> Self {
+        let len = sample.channels.first().map_or(0, Vec::len) as u64;
+
+        Self {
+            channels: sample.channels.clone(),
+            gain: sample.gain,
+            sample_rate: sample.sample_rate,
+            playhead: LoopPlayhead::new(frames, len, handle),
+            position: 0,
+            channel: 0,
+            frame: 0,
+        }
+    }
+}
+
+impl Iterator for LoopedSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            self.frame = self.playhead.source_frame(self.position)?;
+        }
+
+        let sample = self.channels[self.channel][self.frame as usize] * self.gain;
+
+        self.channel += 1;
+        if self.channel == self.channels.len() {
+            self.channel = 0;
+            self.position += 1;
+        }
+
+        Some(sample)
+    }
+}
+
+impl Source for LoopedSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels.len() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// A [`ModalChime`] rendered on the fly.
+struct ModalSource {
+    chime: ModalChime,
+    sample_rate: u32,
+    frame: u64,
+    len: u64,
+}
+
+impl ModalSource {
+    fn new(chime: ModalChime, sample_rate: u32) -> Self {
+        Self {
+            chime,
+            sample_rate,
+            frame: 0,
+            len: chime.len_frames(sample_rate),
+        }
+    }
+}
+
+impl Iterator for ModalSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.frame >= self.len {
+            return None;
+        }
+
+        let sample = self.chime.value_at(self.frame, self.sample_rate);
+        self.frame += 1;
+
+        Some(sample)
+    }
+}
+
+impl Source for ModalSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f64(
+            self.len as f64 / self.sample_rate as f64,
+        ))
+    }
+}
+
+/// A [`SampleStream`] played as it decodes.
+///
+/// If the decoder falls behind, the source plays silence until it catches up.
+struct StreamSource {
+    stream: SampleStream,
+    sample_rate: u32,
+    frame: Vec<f32>,
+    channel: usize,
+}
+
+impl StreamSource {
+    fn new(stream: SampleStream, sample_rate: u32) -> Self {
+        Self {
+            frame: vec![0.0; stream.num_channels()],
+            stream,
+            sample_rate,
+            channel: 0,
+        }
+    }
+}
+
+impl Iterator for StreamSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        // Reading whole frames keeps the channels in order through an underrun.
+        if self.channel == 0 && self.stream.read(&mut self.frame) == 0 {
+            if self.stream.is_finished() {
+                return None;
+            }
+
+            self.frame.fill(0.0);
+        }
+
+        let sample = self.frame[self.channel];
+        self.channel = (self.channel + 1) % self.frame.len();
+
+        Some(sample)
+    }
+}
+
+impl Source for StreamSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.frame.len() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.stream
+            .len_frames()
+            .map(|frames| Duration::from_secs_f64(frames as f64 / self.sample_rate as f64))
+    }
+}
+
+type BoxedSource = Box<dyn Source<Item = f32> + Send>;
 
 #[derive(Component)]
 pub struct BasicRodioSink(Sink);
 
 #[derive(Component)]
-pub struct SpatialRodioSink(SpatialSink);
+pub struct SpatialRodioSink {
+    output: SpatialOutput,
+    /// The volume before occlusion is applied.
+    volume: f32,
+    cutoff: Arc<AtomicU32>,
+}
+
+enum SpatialOutput {
+    /// `rodio`'s two-ear spatializer, used for stereo output.
+    Ears(SpatialSink),
+    /// A plain sink fed by a [`PannedSource`], used for surround output.
+    Panned { sink: Sink, gains: Arc<[AtomicU32]> },
+}
+
+impl SpatialOutput {
+    fn empty(&self) -> bool {
+        match self {
+            SpatialOutput::Ears(sink) => sink.empty(),
+            SpatialOutput::Panned { sink, .. } => sink.empty(),
+        }
+    }
+
+    fn set_volume(&self, volume: f32) {
+        match self {
+            SpatialOutput::Ears(sink) => sink.set_volume(volume),
+            SpatialOutput::Panned { sink, .. } => sink.set_volume(volume),
+        }
+    }
+
+    fn set_speed(&self, speed: f32) {
+        match self {
+            SpatialOutput::Ears(sink) => sink.set_speed(speed),
+            SpatialOutput::Panned { sink, .. } => sink.set_speed(speed),
+        }
+    }
+}
+
+/// Downmixes a source to mono and distributes it across
+/// every output channel according to a set of shared gains.
+///
+/// The gains are stored as `f32` bits so they can be updated
+/// without locking the audio thread.
+struct PannedSource<S> {
+    input: S,
+    gains: Arc<[AtomicU32]>,
+    mono: f32,
+    channel: usize,
+}
+
+impl<S> PannedSource<S> {
+    fn new(input: S, gains: Arc<[AtomicU32]>) -> Self {
+        Self {
+            input,
+            gains,
+            mono: 0.0,
+            channel: 0,
+        }
+    }
+}
+
+impl<S> Iterator for PannedSource<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            let input_channels = self.input.channels();
+
+            let mut sum = 0.0;
+            for _ in 0..input_channels {
+                sum += self.input.next()?;
+            }
+
+            self.mono = sum / input_channels as f32;
+        }
+
+        let gain = f32::from_bits(self.gains[self.channel].load(Ordering::Relaxed));
+        self.channel = (self.channel + 1) % self.gains.len();
+
+        Some(self.mono * gain)
+    }
+}
+
+impl<S> Source for PannedSource<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input
+            .current_frame_len()
+            .map(|len| len / self.input.channels() as usize * self.gains.len())
+    }
+
+    fn channels(&self) -> u16 {
+        self.gains.len() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+/// The surround path for spatial voices: occlusion, then panning across the speakers.
+fn surround<S>(
+    source: S,
+    gains: Arc<[AtomicU32]>,
+    cutoff: Arc<AtomicU32>,
+) -> PannedSource<impl Source<Item = f32> + Send + 'static>
+where
+    S: Source<Item = f32> + Send + 'static,
+{
+    PannedSource::new(occludable(source, cutoff), gains)
+}
+
+/// Wraps a source in a low-pass filter whose cutoff can be
+/// changed from outside the audio thread.
+fn occludable<S>(source: S, cutoff: Arc<AtomicU32>) -> impl Source<Item = f32> + Send + 'static
+where
+    S: Source<Item = f32> + Send + 'static,
+{
+    // The filter is unstable at or above Nyquist, which low sample rates put below 20kHz.
+    let nyquist = source.sample_rate() / 2;
+    let max_cutoff = (nyquist as f32 * 0.95) as u32;
+    let clamp = move |cutoff: u32| cutoff.min(max_cutoff);
+
+    source
+        .low_pass(clamp(OPEN_CUTOFF_HZ as u32))
+        .periodic_access(Duration::from_millis(5), move |filter| {
+            filter.to_low_pass(clamp(cutoff.load(Ordering::Relaxed)));
+        })
+}
+
+/// The decoding weights of an Ambisonic bed, stored as `f32` bits.
+///
+/// Indexed by `output * AMBISONIC_CHANNELS + input`.
+#[derive(Component)]
+pub struct AmbisonicOutput(Arc<[AtomicU32]>);
+
+/// Decodes a four-channel Ambisonic source to the output channels.
+struct AmbisonicSource<S> {
+    input: S,
+    matrix: Arc<[AtomicU32]>,
+    frame: [f32; AMBISONIC_CHANNELS],
+    channel: usize,
+}
+
+impl<S> AmbisonicSource<S> {
+    fn new(input: S, matrix: Arc<[AtomicU32]>) -> Self {
+        Self {
+            input,
+            matrix,
+            frame: [0.0; AMBISONIC_CHANNELS],
+            channel: 0,
+        }
+    }
+
+    fn output_channels(&self) -> usize {
+        self.matrix.len() / AMBISONIC_CHANNELS
+    }
+}
+
+impl<S> Iterator for AmbisonicSource<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            for sample in &mut self.frame {
+                *sample = self.input.next()?;
+            }
+        }
+
+        let weights = &self.matrix[self.channel * AMBISONIC_CHANNELS..];
+        let output = self
+            .frame
+            .iter()
+            .zip(weights)
+            .map(|(sample, weight)| sample * f32::from_bits(weight.load(Ordering::Relaxed)))
+            .sum();
+
+        self.channel = (self.channel + 1) % self.output_channels();
+
+        Some(output)
+    }
+}
+
+impl<S> Source for AmbisonicSource<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input
+            .current_frame_len()
+            .map(|len| len / AMBISONIC_CHANNELS * self.output_channels())
+    }
+
+    fn channels(&self) -> u16 {
+        self.output_channels() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
 
 fn handle_sample_event(
     trigger: Trigger<AudioEvent>,
     context: Res<RodioStreamHandle>,
-    samples: Res<SampleMap>,
+    mut samples: ResMut<SampleMap>,
+    assets: Res<Assets<AudioSample>>,
+    server: Res<AssetServer>,
+    layout: Res<OutputLayout>,
+    clock: Res<RodioClock>,
+    mut pools: ResMut<SamplePools>,
+    mut loader: ResMut<SampleLoader>,
+    streaming: Res<Streaming>,
+    normalization: Res<Normalization>,
     mut commands: Commands,
 ) -> Result {
-    let sample = samples
-        .0
-        .get(trigger.sample)
-        .cloned()
-        .ok_or_else(|| format!("queued unknown sample {}", trigger.sample))?;
+    let trigger = pools.resolve(&trigger);
+
+    let mut loop_handle = None;
+    let mut looping_voice = None;
+    let mut looping_stream = None;
+
+    // Every voice is built on the same base source, so we box it up front.
+    // Streams repeat by decoding the file again, so they never need `repeat_infinite`.
+    let (source, sample_channels): (BoxedSource, u16) =
+        if trigger.handle.is_none() && streaming.should_stream(trigger.sample) {
+            // Failures are recorded in the report, so there's nothing more to say here.
+            let Ok(stream) = SampleStream::open(
+                trigger.sample,
+                clock.sample_rate,
+                trigger.looping,
+                &streaming,
+                &normalization,
+            ) else {
+                return Ok(());
+            };
+            let sample_channels = stream.num_channels() as u16;
+
+            if trigger.looping {
+                looping_stream = Some(LoopingStream::new(trigger.clone()));
+            }
+
+            (
+                Box::new(StreamSource::new(stream, clock.sample_rate)),
+                sample_channels,
+            )
+        } else {
+            let handle = loader.handle(&trigger, &server);
+            if !loader.ensure([&handle], &server)? {
+                loader.defer(trigger, vec![handle]);
+                return Ok(());
+            }
+
+            if trigger.looping {
+                looping_voice = Some(LoopingVoice::new(handle.clone(), trigger.clone()));
+            }
 
+            let prepared = samples.prepare(&handle, &assets)?;
+            let sample_channels = prepared.num_channels();
+
+            let source: BoxedSource = match prepared.loop_frames {
+                Some(frames) if trigger.looping => {
+                    let handle = LoopHandle::default();
+                    let source = LoopedSource::new(prepared, frames, &handle);
+                    loop_handle = Some(handle);
+
+                    Box::new(source)
+                }
+                _ => Box::new(prepared.source(trigger.looping)),
+            };
+
+            (source, sample_channels)
+        };
+
+    let mut new_sound = spawn_voice(
+        source,
+        sample_channels,
+        &trigger,
+        &context,
+        &layout,
+        &clock,
+        &mut commands,
+    )?;
+
+    if let Some(handle) = loop_handle {
+        new_sound.insert(handle);
+    }
+
+    if let Some(voice) = looping_voice {
+        new_sound.insert(voice);
+    }
+
+    if let Some(stream) = looping_stream {
+        new_sound.insert(stream);
+    }
+
+    Ok(())
+}
+
+/// Play a source as a voice, placed and scheduled by the event.
+fn spawn_voice<'a>(
+    source: BoxedSource,
+    sample_channels: u16,
+    trigger: &AudioEvent,
+    context: &RodioStreamHandle,
+    layout: &OutputLayout,
+    clock: &RodioClock,
+    commands: &'a mut Commands,
+) -> Result<EntityCommands<'a>> {
     // This makes both engines sound the same in terms of volume.
     let volume = firewheel::Volume::Linear(trigger.volume).amp();
 
+    let source: BoxedSource = match trigger.start {
+        Some(start) => Box::new(Scheduled::new(source, clock, start)),
+        None => source,
+    };
+
     let mut new_sound = match trigger.position {
+        // Ambisonic beds surround the listener, so their position is ignored.
+        _ if is_ambisonic(trigger, sample_channels as usize) => {
+            let sink = Sink::try_new(&context.0)?;
+            sink.set_volume(volume);
+            sink.set_speed(trigger.speed);
+
+            // The bed stays silent until the first matrix arrives.
+            let matrix: Arc<[AtomicU32]> = (0..layout.0.channel_count() * AMBISONIC_CHANNELS)
+                .map(|_| AtomicU32::new(0f32.to_bits()))
+                .collect();
+
+            sink.append(AmbisonicSource::new(source, matrix.clone()));
+
+            commands.spawn((BasicRodioSink(sink), AmbisonicBed, AmbisonicOutput(matrix)))
+        }
+        Some(position) if layout.0.is_surround() => {
+            let sink = Sink::try_new(&context.0)?;
+            sink.set_volume(volume);
+            sink.set_speed(trigger.speed);
+
+            // Speakers stay silent until the first gains arrive.
+            let gains: Arc<[AtomicU32]> = (0..layout.0.channel_count())
+                .map(|_| AtomicU32::new(0f32.to_bits()))
+                .collect();
+            let cutoff = Arc::new(AtomicU32::new(OPEN_CUTOFF_HZ as u32));
+
+            sink.append(surround(source, gains.clone(), cutoff.clone()));
+
+            commands.spawn((
+                SpatialRodioSink {
+                    output: SpatialOutput::Panned { sink, gains },
+                    volume: trigger.volume,
+                    cutoff,
+                },
+                EmitterPosition(position),
+            ))
+        }
         Some(position) => {
             // here, we massage the distance so this sounds equivalent to firewheel
             let real_distance = position.length();
@@ -119,36 +953,211 @@ fn handle_sample_event(
             sink.set_volume(volume);
             sink.set_speed(trigger.speed);
 
-            if trigger.looping {
-                sink.append(sample.repeat_infinite());
-            } else {
-                sink.append(sample);
-            }
+            let cutoff = Arc::new(AtomicU32::new(OPEN_CUTOFF_HZ as u32));
+            sink.append(occludable(source, cutoff.clone()));
 
-            commands.spawn(SpatialRodioSink(sink))
+            commands.spawn((
+                SpatialRodioSink {
+                    output: SpatialOutput::Ears(sink),
+                    volume: trigger.volume,
+                    cutoff,
+                },
+                EmitterPosition(position),
+            ))
         }
         None => {
             let sink = Sink::try_new(&context.0)?;
             sink.set_volume(volume);
             sink.set_speed(trigger.speed);
-
-            if trigger.looping {
-                sink.append(sample.repeat_infinite());
-            } else {
-                sink.append(sample);
-            }
+            sink.append(source);
 
             commands.spawn(BasicRodioSink(sink))
         }
     };
 
     if let Some(name) = trigger.name {
-        new_sound.insert(Name::new(name));
+        new_sound.insert((Name::new(name), VoiceVolume(trigger.volume)));
+    }
+
+    if let Some(start) = trigger.start {
+        new_sound.insert(ScheduledStart(start));
+    }
+
+    Ok(new_sound)
+}
+
+fn handle_chime_event(
+    trigger: Trigger<ChimeEvent>,
+    context: Res<RodioStreamHandle>,
+    layout: Res<OutputLayout>,
+    clock: Res<RodioClock>,
+    mut commands: Commands,
+) -> Result {
+    let source = Box::new(ModalSource::new(trigger.chime, clock.sample_rate));
+
+    spawn_voice(
+        source,
+        1,
+        &trigger.event,
+        &context,
+        &layout,
+        &clock,
+        &mut commands,
+    )?;
+
+    Ok(())
+}
+
+/// The gain of each stem in a [`StemMixer`], stored as `f32` bits.
+#[derive(Component)]
+pub struct StemOutput(Arc<[AtomicU32]>);
+
+/// Sums a set of stems sample-by-sample.
+///
+/// Since every stem lives in the same source, a single sink
+/// controls their speed and they can never drift apart.
+struct StemMixer<S> {
+    stems: Vec<S>,
+    gains: Arc<[AtomicU32]>,
+}
+
+impl<S> Iterator for StemMixer<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let mut output = None;
+
+        for (stem, gain) in self.stems.iter_mut().zip(self.gains.iter()) {
+            if let Some(sample) = stem.next() {
+                let gain = f32::from_bits(gain.load(Ordering::Relaxed));
+                *output.get_or_insert(0.0) += sample * gain;
+            }
+        }
+
+        output
+    }
+}
+
+impl<S> Source for StemMixer<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.stems.first().and_then(|s| s.current_frame_len())
+    }
+
+    fn channels(&self) -> u16 {
+        self.stems.first().map(|s| s.channels()).unwrap_or(1)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.stems.first().map(|s| s.sample_rate()).unwrap_or(44100)
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+fn handle_layered_event(
+    trigger: Trigger<LayeredAudioEvent>,
+    context: Res<RodioStreamHandle>,
+    mut samples: ResMut<SampleMap>,
+    assets: Res<Assets<AudioSample>>,
+    server: Res<AssetServer>,
+    clock: Res<RodioClock>,
+    intensity: Res<MusicIntensity>,
+    mut loader: ResMut<SampleLoader>,
+    mut commands: Commands,
+) -> Result {
+    let handles: Vec<_> = trigger
+        .stems
+        .iter()
+        .map(|stem| loader.load(stem.sample, &server))
+        .collect();
+
+    if !loader.ensure(&handles, &server)? {
+        loader.defer(trigger.event().clone(), handles);
+        return Ok(());
+    }
+
+    let stem_samples = handles
+        .iter()
+        .map(|handle| assets.get(handle))
+        .collect::<Option<Vec<_>>>()
+        .ok_or("stems finished loading, but aren't available")?;
+    check_stems(trigger.stems, &stem_samples)?;
+
+    // Stems share the first one's normalization gain,
+    // so normalizing them can't change the balance between layers.
+    let shared_gain = stem_samples.first().map_or(1.0, |sample| sample.gain);
+
+    let mut stems = Vec::new();
+    for handle in &handles {
+        let mut source = samples.prepare(handle, &assets)?.source(trigger.looping);
+        source.gain = shared_gain;
+        stems.push(Box::new(source) as BoxedSource);
+    }
+
+    let gains: Arc<[AtomicU32]> = StemGains::at(trigger.stems, intensity.current)
+        .0
+        .iter()
+        .map(|gain| AtomicU32::new(gain.to_bits()))
+        .collect();
+
+    let mixer = StemMixer {
+        stems,
+        gains: gains.clone(),
+    };
+
+    let source: BoxedSource = match trigger.start {
+        Some(start) => Box::new(Scheduled::new(mixer, &clock, start)),
+        None => Box::new(mixer),
+    };
+
+    let sink = Sink::try_new(&context.0)?;
+    sink.set_volume(firewheel::Volume::Linear(trigger.volume).amp());
+    sink.set_speed(trigger.speed);
+    sink.append(source);
+
+    let mut new_sound = commands.spawn((
+        BasicRodioSink(sink),
+        Stems(trigger.stems),
+        StemGains::at(trigger.stems, intensity.current),
+        StemOutput(gains),
+    ));
+
+    if let Some(name) = trigger.name {
+        new_sound.insert((Name::new(name), VoiceVolume(trigger.volume)));
     }
 
     Ok(())
 }
 
+fn handle_speed_event(
+    trigger: Trigger<SpeedEvent>,
+    voices: Query<(&Name, Option<&BasicRodioSink>, Option<&SpatialRodioSink>)>,
+) {
+    let event_name = Name::new(trigger.name);
+
+    for (name, basic, spatial) in &voices {
+        if name != &event_name {
+            continue;
+        }
+
+        if let Some(basic) = basic {
+            basic.0.set_speed(trigger.speed);
+        }
+
+        if let Some(spatial) = spatial {
+            spatial.output.set_speed(trigger.speed);
+        }
+    }
+}
+
 fn monitor_sinks(
     basic_sinks: Query<(Entity, &BasicRodioSink)>,
     spatial_sinks: Query<(Entity, &SpatialRodioSink)>,
@@ -161,7 +1170,7 @@ fn monitor_sinks(
     }
 
     for (entity, sink) in &spatial_sinks {
-        if sink.0.empty() {
+        if sink.output.empty() {
             commands.entity(entity).despawn();
         }
     }
@@ -169,7 +1178,10 @@ fn monitor_sinks(
 
 fn apply_fades(
     mut basic_sinks: Query<(Entity, &BasicRodioSink, &mut VolumeFade), Without<SpatialRodioSink>>,
-    mut spatial_sinks: Query<(Entity, &SpatialRodioSink, &mut VolumeFade), Without<BasicRodioSink>>,
+    mut spatial_sinks: Query<
+        (Entity, &mut SpatialRodioSink, &mut VolumeFade),
+        Without<BasicRodioSink>,
+    >,
     mut commands: Commands,
     time: Res<Time>,
 ) -> Result {
@@ -186,23 +1198,155 @@ fn apply_fades(
         sink.0.set_volume(volume);
 
         if fade.timer.finished() {
-            commands.entity(entity).remove::<VolumeFade>();
+            fade.finish(entity, &mut commands);
         }
     }
 
-    for (entity, sink, mut fade) in &mut spatial_sinks {
+    for (entity, mut sink, mut fade) in &mut spatial_sinks {
         fade.timer.tick(delta);
         let elapsed = fade.timer.elapsed_secs() / fade.timer.duration().as_secs_f32();
 
-        let volume =
-            firewheel::Volume::Linear(fade.event.start.lerp(fade.event.end, elapsed)).amp();
-
-        sink.0.set_volume(volume);
+        // The volume is written to the sink along with the occlusion.
+        sink.volume = fade.event.start.lerp(fade.event.end, elapsed);
 
         if fade.timer.finished() {
-            commands.entity(entity).remove::<VolumeFade>();
+            fade.finish(entity, &mut commands);
         }
     }
 
     Ok(())
 }
+
+fn apply_spatial_params(
+    sinks: Query<
+        (&SpatialRodioSink, &Occlusion, Option<&SpeakerGains>),
+        Or<(
+            Changed<SpatialRodioSink>,
+            Changed<Occlusion>,
+            Changed<SpeakerGains>,
+        )>,
+    >,
+) {
+    for (sink, occlusion, speaker_gains) in &sinks {
+        let volume = firewheel::Volume::Linear(sink.volume * occlusion.gain).amp();
+
+        sink.output.set_volume(volume);
+        sink.cutoff
+            .store(occlusion.cutoff_hz as u32, Ordering::Relaxed);
+
+        if let (SpatialOutput::Panned { gains, .. }, Some(speaker_gains)) =
+            (&sink.output, speaker_gains)
+        {
+            for (gain, value) in gains.iter().zip(speaker_gains.0.iter()) {
+                gain.store(value.to_bits(), Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+fn apply_decode_matrix(beds: Query<(&AmbisonicOutput, &DecodeMatrix), Changed<DecodeMatrix>>) {
+    for (output, matrix) in &beds {
+        for (weights, row) in output.0.chunks(AMBISONIC_CHANNELS).zip(matrix.0.iter()) {
+            for (weight, value) in weights.iter().zip(row) {
+                weight.store(value.to_bits(), Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+fn apply_stem_gains(voices: Query<(&StemOutput, &StemGains), Changed<StemGains>>) {
+    for (output, gains) in &voices {
+        for (gain, value) in output.0.iter().zip(gains.0.iter()) {
+            gain.store(value.to_bits(), Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::panning::{SpeakerLayout, speaker_gains};
+    use rodio::source::SineWave;
+
+    /// Render a second of a tone at the given azimuth through the
+    /// surround path, returning the energy in each output channel.
+    fn render(layout: SpeakerLayout, azimuth: f32) -> Vec<f32> {
+        let radians = azimuth.to_radians();
+        let position = Vec2::new(radians.sin(), radians.cos()) * 5.0;
+
+        let gains: Arc<[AtomicU32]> = speaker_gains(layout, position)
+            .into_iter()
+            .map(|gain| AtomicU32::new(gain.to_bits()))
+            .collect();
+        let cutoff = Arc::new(AtomicU32::new(OPEN_CUTOFF_HZ as u32));
+        let source = surround(SineWave::new(440.0), gains, cutoff);
+
+        let channels = source.channels() as usize;
+        let frames = source.sample_rate() as usize;
+
+        let mut energy = vec![0.0; channels];
+        for (i, sample) in source.take(frames * channels).enumerate() {
+            energy[i % channels] += sample * sample;
+        }
+
+        energy
+    }
+
+    /// Check that the named channels hold all but a trace of the energy.
+    fn assert_energy_in(energy: &[f32], channels: &[usize], layout: SpeakerLayout, azimuth: f32) {
+        let total: f32 = energy.iter().sum();
+        assert!(total > 0.0, "{layout:?} at {azimuth} degrees is silent");
+
+        for (channel, channel_energy) in energy.iter().enumerate() {
+            let share = channel_energy / total;
+
+            if channels.contains(&channel) {
+                assert!(
+                    share > 0.9 / channels.len() as f32,
+                    "{layout:?} at {azimuth} degrees only puts {share} of the energy in channel {channel}"
+                );
+            } else {
+                assert!(
+                    share < 1e-4,
+                    "{layout:?} at {azimuth} degrees leaks {share} of the energy into channel {channel}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sources_at_a_speaker_play_from_that_speaker() {
+        for layout in [SpeakerLayout::Surround51, SpeakerLayout::Surround71] {
+            for (channel, azimuth) in layout.azimuths().iter().enumerate() {
+                let Some(azimuth) = *azimuth else {
+                    continue;
+                };
+
+                assert_energy_in(&render(layout, azimuth), &[channel], layout, azimuth);
+            }
+        }
+    }
+
+    #[test]
+    fn sources_between_speakers_play_from_both() {
+        // Front left and center, then front right and the right surround.
+        let layout = SpeakerLayout::Surround51;
+
+        let energy = render(layout, -15.0);
+        assert_energy_in(&energy, &[0, 2], layout, -15.0);
+        assert!((energy[0] - energy[2]).abs() / (energy[0] + energy[2]) < 0.01);
+
+        let energy = render(layout, 70.0);
+        assert_energy_in(&energy, &[1, 5], layout, 70.0);
+        assert!((energy[1] - energy[5]).abs() / (energy[1] + energy[5]) < 0.01);
+    }
+
+    #[test]
+    fn lfe_receives_nothing() {
+        for layout in [SpeakerLayout::Surround51, SpeakerLayout::Surround71] {
+            for azimuth in (0..360).step_by(15) {
+                assert_eq!(render(layout, azimuth as f32)[3], 0.0);
+            }
+        }
+    }
+}
@@ -1,17 +1,68 @@
 use bevy::prelude::*;
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use audio::{
+    cache::SampleCache,
+    loudness::Normalization,
+    modal::ChimeVoice,
+    panning::{OutputLayout, SpeakerLayout},
+    report::StrictSamples,
+};
 
 mod audio;
 mod engine;
 mod narrative;
 mod textbox;
+mod validate;
 
 /// Evaluate the Firewheel and `rodio` audio engines
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Select the engine to evaluate
-    engine: Engine,
+    #[arg(required = true)]
+    engine: Option<Engine>,
+
+    /// Select the output speaker layout
+    #[arg(long, value_enum, default_value = "stereo")]
+    layout: SpeakerLayout,
+
+    /// Select how the chimes are voiced
+    #[arg(long, value_enum, default_value = "sampled")]
+    chimes: Chimes,
+
+    /// Exit if any sample fails to load, checking every referenced sample at startup
+    #[arg(long)]
+    strict: bool,
+
+    /// Normalize samples to an integrated loudness, in LUFS
+    #[arg(
+        long,
+        value_name = "LUFS",
+        num_args = 0..=1,
+        default_missing_value = "-18",
+        allow_negative_numbers = true
+    )]
+    normalize: Option<f32>,
+
+    /// Decode every sample from scratch, without reading or writing the sample cache
+    #[arg(long)]
+    no_sample_cache: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check that every sample the demo refers to exists and decodes
+    Validate,
 }
 
 #[derive(ValueEnum, Clone, Debug)]
@@ -20,15 +71,39 @@ enum Engine {
     Rodio,
 }
 
-fn main() {
+#[derive(ValueEnum, Clone, Debug)]
+enum Chimes {
+    /// Play the recorded chimes
+    Sampled,
+    /// Synthesize chimes that follow the music's key
+    Synthesized,
+}
+
+fn main() -> AppExit {
     let args = Args::parse();
+
+    if let Some(Command::Validate) = args.command {
+        return validate::validate();
+    }
+
     bevy::ecs::error::GLOBAL_ERROR_HANDLER
         .set(bevy::ecs::error::warn)
         .unwrap();
 
     let mut app = App::new();
 
+    if args.no_sample_cache {
+        app.insert_resource(SampleCache { directory: None });
+    }
+
     app.insert_resource(ClearColor(Color::srgb(0.1, 0.1, 0.1)))
+        .insert_resource(OutputLayout(args.layout))
+        .insert_resource(match args.chimes {
+            Chimes::Sampled => ChimeVoice::Sampled,
+            Chimes::Synthesized => ChimeVoice::synthesized(),
+        })
+        .insert_resource(StrictSamples(args.strict))
+        .insert_resource(Normalization::with_target(args.normalize))
         .add_plugins((
             DefaultPlugins
                 .set(TaskPoolPlugin {
@@ -53,16 +128,17 @@ fn main() {
         ));
 
     match args.engine {
-        Engine::Firewheel => {
+        Some(Engine::Firewheel) => {
             app.add_plugins(engine::firewheel_engine::FirewheelPlugin);
         }
-        Engine::Rodio => {
+        Some(Engine::Rodio) => {
             app.add_plugins(engine::rodio_engine::RodioPlugin);
         }
+        None => unreachable!("an engine is required without a subcommand"),
     }
 
     app.add_systems(Startup, |mut commands: Commands| {
         commands.spawn(Camera2d);
     })
-    .run();
+    .run()
 }
@@ -1,62 +1,64 @@
 use bevy::prelude::*;
-use rand::Rng;
-use std::time::Duration;
 
-use crate::audio::{AudioEvent, VolumeFadeEvent, repeater::SoundRepeater};
+use crate::{
+    audio::{
+        ambience::AmbienceEvent,
+        chimes::{CHIME_SAMPLES, WindFollows},
+        footsteps::{LEAF_STEPS, WATER_STEPS},
+        loading::{RegisterSampleGroup, SampleGroupEvent, concat_samples, count_samples},
+        midi::Instrument,
+        occlusion::{Occluder, OccluderShape},
+    },
+    textbox::sequence::TEXTBOX_SAMPLES,
+};
 
 mod sequences;
 
+pub use sequences::{SCRIPT_MIDI, SCRIPT_SAMPLES, SCRIPT_STREAMS};
+
 pub fn narrative_plugin(app: &mut App) {
     app.add_plugins(sequences::sequences_plugin)
+        .register_sample_group(FOREST, FOREST_SAMPLES)
+        .reference_samples(SCRIPT_STREAMS.iter().copied())
+        .reference_samples(Instrument::CHIMES.samples.iter().map(|(_, sample)| *sample))
         .add_systems(Startup, startup);
 }
 
+/// Everything the story plays, so nothing waits on a decode mid-scene.
+///
+/// The long music and ambience beds are streamed instead, and the
+/// soundscape's one-shots load the first time they're called for.
+const FOREST: &str = "forest";
+
+const FOREST_LISTS: &[&[&str]] = &[
+    SCRIPT_SAMPLES,
+    TEXTBOX_SAMPLES,
+    LEAF_STEPS,
+    WATER_STEPS,
+    CHIME_SAMPLES,
+];
+
+pub const FOREST_SAMPLES: &[&str] =
+    &concat_samples::<{ count_samples(FOREST_LISTS) }>(FOREST_LISTS);
+
 fn startup(mut commands: Commands) {
-    let fade_in_time = 2.5;
+    commands.trigger(SampleGroupEvent::Load(FOREST));
 
-    commands.trigger(AudioEvent {
-        sample: "pine_trees.ogg",
-        looping: true,
-        name: Some("pine"),
-        volume: 0.0,
-        ..Default::default()
+    // We fade in the ambience for a nice startup vibe
+    commands.trigger(AmbienceEvent::Switch {
+        soundscape: "forest",
+        seconds: 2.5,
     });
 
-    // We fade in the ambience for a nice startup vibe
-    commands.trigger(VolumeFadeEvent {
+    // The wind picks up along with the sound of the trees.
+    commands.insert_resource(WindFollows {
         name: "pine",
-        start: 0.0,
-        end: 1.1,
-        seconds: fade_in_time,
+        scale: 0.7,
     });
 
-    commands.trigger(AudioEvent {
-        sample: "nightingale.ogg",
-        looping: true,
-        position: Some(Vec2::new(15.0, 10.0)),
-        volume: 0.0,
-        name: Some("nightingale"),
+    // The crow calls from just behind the tree line.
+    commands.spawn(Occluder {
+        shape: OccluderShape::Segment(Vec2::new(-25.0, 10.0), Vec2::new(-5.0, 10.0)),
         ..Default::default()
     });
-
-    commands.trigger(VolumeFadeEvent {
-        name: "nightingale",
-        start: 0.0,
-        end: 0.9,
-        seconds: fade_in_time,
-    });
-
-    commands.spawn(SoundRepeater::new(
-        || AudioEvent {
-            sample: "caw.ogg",
-            position: Some(Vec2::new(-15.0, 15.0)),
-            ..Default::default()
-        },
-        || {
-            let mut rng = rand::thread_rng();
-            let duration = rng.gen_range(10.0..25.0);
-
-            Duration::from_secs_f32(duration)
-        },
-    ));
 }
@@ -15,8 +15,12 @@ use bevy_sequence::prelude::{FragmentExt, IntoFragment, spawn_root};
 use crate::{
     audio::{
         AudioEvent, VolumeFadeEvent,
-        chimes::{ChimesEnable, ChimesTimer},
-        footsteps::WalkEvent,
+        chimes::{ChimesEnable, WindChimes},
+        footsteps::{FootstepEmitter, Surface, Walk, WalkEvent},
+        midi::{Instrument, MidiEvent, MidiSequence},
+        modal::Scale,
+        music::{MUSIC_NAME, MusicEvent, MusicTrack},
+        stems::{IntensityEvent, MusicStem},
     },
     textbox::sequence::{AudioSequence, CharacterFragment, despawn_textbox, dynamic},
 };
@@ -25,10 +29,130 @@ pub fn sequences_plugin(app: &mut App) {
     app.add_systems(Startup, |mut commands: Commands| {
         spawn_root(demo().always().once(), &mut commands);
     })
-    .add_systems(Update, tick_watch)
+    .add_systems(Update, (tick_watch, approach))
     .register_pretty_style("yellow", |_| Color::from(palettes::basic::YELLOW));
 }
 
+/// Every file the scripts play.
+///
+/// Scripts name files through [`SCRIPT`] rather than with literals, and
+/// the lists below take their files from it field by field, so a new
+/// file can't be added without deciding which list it belongs in.
+#[derive(Clone, Copy)]
+struct ScriptFiles {
+    splash: &'static str,
+    towel: &'static str,
+    zipper: &'static str,
+    /// The stems of Aster's theme.
+    aster_base: &'static str,
+    aster_air: &'static str,
+    /// The full mix of Aster's theme, which its stems replace.
+    aster: &'static str,
+    creek: &'static str,
+    farewell: &'static str,
+}
+
+const SCRIPT: ScriptFiles = ScriptFiles {
+    splash: "splash.ogg",
+    towel: "towel.ogg",
+    zipper: "zipper.ogg",
+    aster_base: "stems/aster-base.flac",
+    aster_air: "stems/aster-air.flac",
+    aster: "aster.ogg",
+    creek: "creek.ogg",
+    farewell: "midi/farewell.mid",
+};
+
+impl ScriptFiles {
+    /// Samples that are decoded up front and preloaded with the forest.
+    ///
+    /// The theme's stems are among them, since the music can't start
+    /// until every layer has been decoded.
+    const fn samples(self) -> [&'static str; 5] {
+        let Self {
+            splash,
+            towel,
+            zipper,
+            aster_base,
+            aster_air,
+            aster: _,
+            creek: _,
+            farewell: _,
+        } = self;
+
+        [splash, towel, zipper, aster_base, aster_air]
+    }
+
+    /// Long samples, which are streamed from disk.
+    const fn streams(self) -> [&'static str; 2] {
+        let Self {
+            aster,
+            creek,
+            splash: _,
+            towel: _,
+            zipper: _,
+            aster_base: _,
+            aster_air: _,
+            farewell: _,
+        } = self;
+
+        [aster, creek]
+    }
+
+    const fn midi(self) -> [&'static str; 1] {
+        let Self {
+            farewell,
+            splash: _,
+            towel: _,
+            zipper: _,
+            aster_base: _,
+            aster_air: _,
+            aster: _,
+            creek: _,
+        } = self;
+
+        [farewell]
+    }
+}
+
+/// Every sample the scripts play directly, other than the streamed ones.
+pub const SCRIPT_SAMPLES: &[&str] = &SCRIPT.samples();
+
+/// The long samples the scripts refer to, which are streamed from disk.
+pub const SCRIPT_STREAMS: &[&str] = &SCRIPT.streams();
+
+/// The MIDI files the scripts play.
+pub const SCRIPT_MIDI: &[&str] = &SCRIPT.midi();
+
+/// Aster's theme, split at 1 kHz into two stems that sum back to the
+/// original mix. The air above the split comes in as the music builds
+/// around the creek.
+const ASTER_THEME: MusicTrack = MusicTrack {
+    sample: SCRIPT.aster,
+    stems: &[
+        MusicStem {
+            sample: SCRIPT.aster_base,
+            threshold: 0.0,
+            volume: 1.0,
+        },
+        MusicStem {
+            sample: SCRIPT.aster_air,
+            threshold: 0.4,
+            volume: 1.0,
+        },
+    ],
+    bpm: 100.0,
+    beats_per_bar: 4,
+    offset: 0.0,
+    volume: 0.52,
+    speed: 0.80,
+    // The theme is in D, and the pentatonic keeps synthesized chimes consonant.
+    key: Some(Scale {
+        root: 293.66,
+        steps: Scale::MAJOR_PENTATONIC,
+    }),
+};
+
 fn demo() -> impl IntoFragment<AudioSequence> {
     (
         intro().on_end(despawn_textbox),
@@ -44,12 +168,20 @@ fn intro() -> impl IntoFragment<AudioSequence> {
         "The moon peeks behind the clouds.",
         "The wind blows through the tall trees.",
         1.5,
-        "You see someone walking towards you.".on_start(trigger(WalkEvent::Start(0.5))),
-        "Oh no<0.2>... [1]<1>he wants to <0.5>`talk to you`[shake(1, 3)]..."
-            .on_start(trigger(WalkEvent::Start(0.75)))
-            .on_end(trigger(WalkEvent::Start(1.0))),
-        1.5.on_end(trigger(WalkEvent::Stop)),
-        1.5,
+        "You see someone walking towards you.".on_start(|mut commands: Commands| {
+            commands.spawn((
+                Name::new("stranger"),
+                Stranger,
+                Transform::from_xyz(-4.0, 12.0, 0.0),
+                FootstepEmitter::new(1.0),
+                Approach {
+                    target: Vec2::new(0.0, 2.0),
+                    speed: 1.1,
+                },
+            ));
+        }),
+        "Oh no<0.2>... [1]<1>he wants to <0.5>`talk to you`[shake(1, 3)]...",
+        3.0,
         "Hey there!".stranger().on_end(|mut commands: Commands| {
             commands.spawn(AsterWatch {
                 timer: Stopwatch::new(),
@@ -61,7 +193,7 @@ fn intro() -> impl IntoFragment<AudioSequence> {
         1.0,
         "You can't think of an excuse, [0.5]so unfortunately you have to accept."
             .narrator()
-            .on_end(trigger(WalkEvent::Start(1.0))),
+            .on_end(trigger(WalkEvent::Start(Walk::new(1.0)))),
         3.0,
     )
 }
@@ -71,25 +203,19 @@ fn creek() -> impl IntoFragment<AudioSequence> {
         "My name's `Aster|yellow`.[1] Pleased to meet you!"
             .stranger()
             // queue the music!
-            .on_end(trigger(AudioEvent {
-                sample: "aster.ogg",
-                volume: 0.52,
-                speed: 0.80,
-                looping: true,
-                name: Some("music"),
-                ..Default::default()
-            })),
+            .on_end(trigger(MusicEvent::play(ASTER_THEME))),
         2.5,
         "Aster runs his hand absent-mindedly though some chimes."
             .narrator()
             .on_start(|mut commands: Commands| {
-                commands.spawn(ChimesTimer::new(0.65, Vec2::new(4.0, 3.0)));
+                commands.spawn(WindChimes::new(Vec2::new(4.0, 3.0)).with_push(0.65));
             }),
         "(Who put chimes out here?)".on_start(trigger(VolumeFadeEvent {
             name: "pine",
             start: 1.1,
             end: 1.3,
             seconds: 5.0,
+            ..Default::default()
         })),
         3.0,
         "Don't you love the sound of pine trees in the wind?".aster(),
@@ -98,7 +224,7 @@ fn creek() -> impl IntoFragment<AudioSequence> {
             let name = "creek";
 
             commands.trigger(AudioEvent {
-                sample: "creek.ogg",
+                sample: SCRIPT.creek,
                 volume: 0.0,
                 looping: true,
                 name: Some(name),
@@ -110,6 +236,7 @@ fn creek() -> impl IntoFragment<AudioSequence> {
                 start: 0.0,
                 end: 0.4,
                 seconds: 5.0,
+                ..Default::default()
             });
 
             commands.trigger(VolumeFadeEvent {
@@ -117,27 +244,46 @@ fn creek() -> impl IntoFragment<AudioSequence> {
                 start: 1.3,
                 end: 1.1,
                 seconds: 5.0,
+                ..Default::default()
             });
         })
         .on_end(trigger(WalkEvent::Stop)),
         "Oh look![1] A `little`[wave] river!".aster(),
         "Aster deftly crosses the stream,[0.5] prancing between the little rocks.".narrator(),
         1.0,
-        "Now it's your turn.[1]<0.5> `Oh man...`[shake]",
-        1.5.on_end(trigger(AudioEvent {
-            sample: "splash.ogg",
-            volume: 1.0,
-            ..Default::default()
-        })),
+        "Now it's your turn.[1]<0.5> `Oh man...`[shake]".on_start(|mut commands: Commands| {
+            commands.trigger(WalkEvent::Start(
+                Walk::new(0.8).on(Surface::Water).at_speed(1.1),
+            ));
+
+            // The music builds as you cross.
+            commands.trigger(IntensityEvent {
+                target: 1.0,
+                seconds: 3.0,
+            });
+        }),
+        1.5.on_end(|mut commands: Commands| {
+            commands.trigger(WalkEvent::Stop);
+            commands.trigger(AudioEvent {
+                sample: SCRIPT.splash,
+                volume: 1.0,
+                ..Default::default()
+            });
+        }),
         0.7,
         "Oh no!".aster(),
         "Naturally, you slipped on the last rock.[0.5] Aster helps pull you out."
             .narrator()
+            .on_start(trigger(IntensityEvent {
+                target: 0.0,
+                seconds: 6.0,
+            }))
             .on_end(trigger(VolumeFadeEvent {
                 name: "creek",
                 start: 0.4,
                 end: 0.30,
                 seconds: 4.0,
+                ..Default::default()
             })),
         1.0,
     )
@@ -148,7 +294,7 @@ fn end() -> impl IntoFragment<AudioSequence> {
         "Here,[0.5] I always bring this just in case."
             .aster()
             .on_end(trigger(AudioEvent {
-                sample: "zipper.ogg",
+                sample: SCRIPT.zipper,
                 volume: 0.7,
                 ..Default::default()
             })),
@@ -156,17 +302,27 @@ fn end() -> impl IntoFragment<AudioSequence> {
         "He fishes around in his bag for a moment,[0.5] and hands you a towel.".narrator(),
         "You dry yourself off, wondering what kind of contingencies Aster's planning for."
             .on_start(trigger(AudioEvent {
-                sample: "towel.ogg",
+                sample: SCRIPT.towel,
                 ..Default::default()
             })),
         2.0,
         "You go to hand the towel back,[0.5] except<0.2>...[1] <1>you don't [0.5]see him anywhere."
-            .on_start(trigger(VolumeFadeEvent {
-                name: "music",
-                start: 0.52,
-                end: 0.0,
-                seconds: 6.0,
-            })),
+            .on_start(
+                |stranger: Query<Entity, With<Stranger>>, mut commands: Commands| {
+                    commands.trigger(VolumeFadeEvent {
+                        name: MUSIC_NAME,
+                        start: ASTER_THEME.volume,
+                        end: 0.0,
+                        seconds: 6.0,
+                        ..Default::default()
+                    });
+
+                    // Aster's gone, footsteps and all.
+                    for entity in &stranger {
+                        commands.entity(entity).despawn();
+                    }
+                },
+            ),
         2.0,
         "Huh...",
         2.0,
@@ -182,8 +338,12 @@ fn end() -> impl IntoFragment<AudioSequence> {
             )
         }),
         3.0,
-        "Well,[1] you had best head home too.",
-        "(That's all! You can also mess with the chimes by pressing C.)".on_start(
+        "Well,[1] you had best head home too.".on_start(trigger(MidiEvent::Play(
+            MidiSequence::new(SCRIPT.farewell, Instrument::CHIMES)
+                .with_volume(0.7)
+                .at(Vec2::new(4.0, 3.0)),
+        ))),
+        "(That's all! You can also play the chimes with your mouse, or press C.)".on_start(
             |mut commands: Commands| {
                 commands.spawn(ChimesEnable);
             },
@@ -198,6 +358,10 @@ fn trigger<E: Event + Clone>(event: E) -> impl Fn(Commands) + Send + Sync + 'sta
     }
 }
 
+/// Aster, who walks up at the start and slips away at the end.
+#[derive(Component)]
+struct Stranger;
+
 /// I thought this would be a cute gag.
 #[derive(Component)]
 struct AsterWatch {
@@ -209,3 +373,30 @@ fn tick_watch(mut watches: Query<&mut AsterWatch>, time: Res<Time>) {
         watch.timer.tick(time.delta());
     }
 }
+
+/// Walk an entity towards a point at a steady speed.
+#[derive(Component)]
+struct Approach {
+    target: Vec2,
+    /// The walking speed in meters per second.
+    speed: f32,
+}
+
+fn approach(
+    mut walkers: Query<(Entity, &mut Transform, &Approach)>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    for (entity, mut transform, approach) in &mut walkers {
+        let position = transform.translation.xy();
+        let remaining = approach.target - position;
+        let step = approach.speed * time.delta_secs();
+
+        if remaining.length() <= step {
+            transform.translation = approach.target.extend(transform.translation.z);
+            commands.entity(entity).remove::<Approach>();
+        } else {
+            transform.translation += (remaining.normalize() * step).extend(0.0);
+        }
+    }
+}
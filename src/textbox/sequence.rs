@@ -9,10 +9,17 @@ use std::{marker::PhantomData, time::Duration};
 
 use crate::audio::AudioEvent;
 
+const CLICK: &str = "click.ogg";
+const TALK: &str = "talk.wav";
+const TALK_LOW: &str = "talk-low.wav";
+
+/// Every sample the textbox plays.
+pub const TEXTBOX_SAMPLES: &[&str] = &[CLICK, TALK, TALK_LOW];
+
 pub fn sequence_plugin(app: &mut App) {
     app.insert_resource(Character {
         name: None,
-        text_sound: "talk-low.wav",
+        text_sound: TALK_LOW,
     })
     .add_event::<FragmentEvent<AudioSequence>>()
     .add_systems(Startup, generate_triangle)
@@ -191,7 +198,7 @@ fn textbox_handler(
         }
 
         commands.trigger(AudioEvent {
-            sample: "click.ogg",
+            sample: CLICK,
             volume: 0.9,
             ..Default::default()
         });
@@ -232,21 +239,21 @@ where
     fn narrator(self) -> impl IntoFragment<AudioSequence> {
         self.on_start(|mut character: ResMut<Character>| {
             character.name = None;
-            character.text_sound = "talk-low.wav";
+            character.text_sound = TALK_LOW;
         })
     }
 
     fn stranger(self) -> impl IntoFragment<AudioSequence> {
         self.on_start(|mut character: ResMut<Character>| {
             character.name = Some("Stranger");
-            character.text_sound = "talk.wav";
+            character.text_sound = TALK;
         })
     }
 
     fn aster(self) -> impl IntoFragment<AudioSequence> {
         self.on_start(|mut character: ResMut<Character>| {
             character.name = Some("Aster");
-            character.text_sound = "talk.wav";
+            character.text_sound = TALK;
         })
     }
 }
@@ -0,0 +1,194 @@
+//! The `validate` subcommand.
+//!
+//! Samples are named with plain strings, so a typo only shows up when
+//! the sound is due to play. This gathers every sample and MIDI file the
+//! demo refers to, from the lists each module exposes and the ambience
+//! files, and checks that each one exists and decodes.
+//!
+//! The same checks run as a test, so a typo fails CI.
+
+use bevy::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Cursor,
+    path::Path,
+};
+
+use crate::{
+    audio::{
+        ambience::read_soundscapes,
+        chimes::CHIME_SAMPLES,
+        footsteps::{LEAF_STEPS, WATER_STEPS},
+        loops::find_loop_region,
+        midi::{Instrument, read_notes},
+        report::{SampleFailure, SampleLoadError},
+        sample::AUDIO_EXTENSIONS,
+    },
+    narrative::{FOREST_SAMPLES, SCRIPT_MIDI, SCRIPT_SAMPLES, SCRIPT_STREAMS},
+    textbox::sequence::TEXTBOX_SAMPLES,
+};
+
+/// Check every referenced sample, printing what's wrong.
+pub fn validate() -> AppExit {
+    let validation = check();
+
+    for error in &validation.errors {
+        eprintln!("{error}");
+    }
+
+    if validation.errors.is_empty() {
+        println!("all {} referenced files are valid", validation.checked);
+        AppExit::Success
+    } else {
+        eprintln!(
+            "{} problems found in {} referenced files",
+            validation.errors.len(),
+            validation.checked
+        );
+        AppExit::error()
+    }
+}
+
+/// The outcome of checking everything the demo refers to.
+struct Validation {
+    /// The number of distinct files checked.
+    checked: usize,
+    errors: Vec<String>,
+}
+
+fn check() -> Validation {
+    // Each file, along with everywhere it's referenced.
+    let mut samples: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut reference = |sample: &str, source: &str| {
+        samples
+            .entry(sample.to_owned())
+            .or_default()
+            .insert(source.to_owned());
+    };
+
+    let lists: &[(&[&str], &str)] = &[
+        (SCRIPT_SAMPLES, "the scripts"),
+        (SCRIPT_STREAMS, "the scripts"),
+        (TEXTBOX_SAMPLES, "the textbox"),
+        (LEAF_STEPS, "footsteps"),
+        (WATER_STEPS, "footsteps"),
+        (CHIME_SAMPLES, "chimes"),
+        (FOREST_SAMPLES, "the forest preload group"),
+    ];
+
+    for (list, source) in lists {
+        for sample in *list {
+            reference(sample, source);
+        }
+    }
+
+    for (_, sample) in Instrument::CHIMES.samples {
+        reference(sample, "the chime instrument");
+    }
+
+    let mut errors = Vec::new();
+
+    for (name, soundscape) in read_soundscapes() {
+        let source = format!("ambience/{name}.ron");
+
+        match soundscape {
+            Ok(soundscape) => {
+                for bed in &soundscape.beds {
+                    reference(bed.sample, &source);
+                }
+
+                for one_shot in &soundscape.one_shots {
+                    for sample in &one_shot.samples {
+                        reference(sample, &source);
+                    }
+                }
+            }
+            Err(e) => errors.push(format!("failed to parse {source}: {e}")),
+        }
+    }
+
+    for (sample, sources) in &samples {
+        if let Err(e) = check_sample(sample) {
+            let sources: Vec<_> = sources.iter().map(String::as_str).collect();
+            errors.push(format!("{e}, referenced by {}", sources.join(", ")));
+        }
+    }
+
+    for midi in SCRIPT_MIDI {
+        if let Err(e) = check_midi(midi) {
+            errors.push(format!("{e}, referenced by the scripts"));
+        }
+    }
+
+    Validation {
+        checked: samples.len() + SCRIPT_MIDI.len(),
+        errors,
+    }
+}
+
+fn is_audio_path(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Make sure a sample exists, decodes, and has a readable loop region.
+fn check_sample(sample: &str) -> Result<(), SampleLoadError> {
+    let error = |reason| SampleLoadError::new(sample, reason);
+
+    if !is_audio_path(sample) {
+        return Err(error(SampleFailure::NotAudio));
+    }
+
+    let path = Path::new("assets").join(sample);
+    let bytes = std::fs::read(&path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => error(SampleFailure::Missing),
+        _ => error(SampleFailure::Decode(e.to_string())),
+    })?;
+
+    let mut sidecar_path = path.into_os_string();
+    sidecar_path.push(".loop");
+    let sidecar = std::fs::read_to_string(sidecar_path).ok();
+
+    find_loop_region(&bytes, sidecar.as_deref())
+        .map_err(|_| error(SampleFailure::Decode("malformed loop file".into())))?;
+
+    symphonium::SymphoniumLoader::new()
+        .load_f32_from_source(
+            Box::new(Cursor::new(bytes)),
+            None,
+            None,
+            Default::default(),
+            None,
+        )
+        .map_err(|e| error(SampleFailure::Decode(e.to_string())))?;
+
+    Ok(())
+}
+
+/// Make sure a MIDI file exists and parses.
+fn check_midi(file: &str) -> Result<(), String> {
+    let bytes = std::fs::read(Path::new("assets").join(file))
+        .map_err(|e| format!("failed to load MIDI file \"{file}\": {e}"))?;
+
+    read_notes(&bytes).map_err(|e| format!("failed to parse MIDI file \"{file}\": {e}"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_referenced_file_is_valid() {
+        let validation = check();
+
+        assert!(
+            validation.errors.is_empty(),
+            "{}",
+            validation.errors.join("\n")
+        );
+    }
+}